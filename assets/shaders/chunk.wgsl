@@ -0,0 +1,93 @@
+// Draws whole chunks as single quads, looking each cell's material up in a
+// storage buffer instead of drawing one instance per cell.
+
+override chunk_width: u32;
+override chunk_height: u32;
+
+// Low bits of a packed cell are the material, the high byte its shade.
+const MATERIAL_BITS: u32 = 24u;
+
+struct CameraUniform {
+    view_proj: mat4x4f,
+    position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Material {
+    color: vec4<f32>,
+    gradient: vec4<f32>,
+    variation: f32,
+    texture_offset: u32,
+    texture_width: u32,
+    texture_height: u32,
+};
+
+// Packed cells, `chunk_width * chunk_height` per slot. Material 0 is empty,
+// everything else is an index into `materials` plus one.
+@group(1) @binding(0) var<storage, read> cells: array<u32>;
+@group(1) @binding(1) var<storage, read> materials: array<Material>;
+// Material textures as packed RGBA8, rows top to bottom.
+@group(1) @binding(2) var<storage, read> texels: array<u32>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct ChunkInput {
+    @location(5) origin: vec2<f32>,
+    @location(6) slot: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) @interpolate(flat) slot: u32,
+    @location(2) @interpolate(flat) origin: vec2<i32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, chunk: ChunkInput) -> VertexOutput {
+    let size = vec2<f32>(f32(chunk_width), f32(chunk_height));
+    // Cells are centred on integer positions, so the chunk starts half a cell
+    // before its origin.
+    let local = (model.position.xy + 0.5) * size;
+    let world = chunk.origin - 0.5 + local;
+
+    var out: VertexOutput;
+    out.local = local;
+    out.slot = chunk.slot;
+    out.origin = vec2<i32>(chunk.origin);
+    out.clip_position = (vec4<f32>(world, 0.0, 1.0) - camera.position) * camera.view_proj;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cell = min(vec2<u32>(floor(in.local)), vec2<u32>(chunk_width - 1u, chunk_height - 1u));
+    let index = in.slot * chunk_width * chunk_height + cell.y * chunk_width + cell.x;
+    let packed = cells[index];
+    let id = packed & ((1u << MATERIAL_BITS) - 1u);
+    if id == 0u {
+        discard;
+    }
+    let material = materials[id - 1u];
+    let shade = f32(packed >> MATERIAL_BITS) / 255.0;
+
+    var color = mix(material.color, material.gradient, shade);
+    // Brightness uses other bits of the shade than the gradient, so the two
+    // don't move in lockstep.
+    let jitter = fract(shade * 17.0) * 2.0 - 1.0;
+    color = vec4<f32>(color.rgb * (1.0 + material.variation * jitter), color.a);
+
+    if material.texture_width > 0u {
+        let size = vec2<i32>(i32(material.texture_width), i32(material.texture_height));
+        let world = in.origin + vec2<i32>(cell);
+        let texel = ((world % size) + size) % size;
+        // World y points up, texture rows go down.
+        let row = u32(size.y - 1 - texel.y);
+        color *= unpack4x8unorm(texels[material.texture_offset + row * material.texture_width + u32(texel.x)]);
+    }
+    return color;
+}
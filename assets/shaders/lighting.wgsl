@@ -0,0 +1,59 @@
+// Composites the light map over the cell layer: `fs_shade` multiplies what's
+// drawn with the light reaching it, `fs_glow` adds emitted light on top.
+
+struct CameraUniform {
+    view_proj: mat4x4f,
+    position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LightUniform {
+    // Cell at the bottom left texel and the map size in cells.
+    origin: vec2<f32>,
+    size: vec2<f32>,
+    // Sky colour, with the light of unlit cells in w.
+    ambient: vec4<f32>,
+    glow: f32,
+};
+
+@group(1) @binding(0) var<uniform> light: LightUniform;
+// Emitted light in rgb, sky light in a, rows bottom to top.
+@group(1) @binding(1) var light_map: texture_2d<f32>;
+@group(1) @binding(2) var light_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+        vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0),
+    );
+    let uv = corners[index];
+    // Cells are centred on integer positions, so the map starts half a cell
+    // before its origin.
+    let world = light.origin - 0.5 + uv * light.size;
+
+    var out: VertexOutput;
+    out.uv = uv;
+    out.clip_position = (vec4<f32>(world, 0.0, 1.0) - camera.position) * camera.view_proj;
+    return out;
+}
+
+@fragment
+fn fs_shade(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(light_map, light_sampler, in.uv);
+    let lit = max(light.ambient.rgb * texel.a, texel.rgb);
+    return vec4<f32>(max(lit, vec3<f32>(light.ambient.w)), 1.0);
+}
+
+@fragment
+fn fs_glow(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(light_map, light_sampler, in.uv);
+    return vec4<f32>(texel.rgb * light.glow, 1.0);
+}
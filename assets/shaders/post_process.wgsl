@@ -0,0 +1,132 @@
+// Fullscreen passes run by `PostProcessor` over the HDR scene. Every pass
+// reads `source`, some a second texture in `extra`, and takes its settings
+// from `params`.
+
+struct PostUniform {
+    params: vec4<f32>,
+    // Texel size of `source`.
+    texel_size: vec2<f32>,
+    // Size in pixels of the target drawn to.
+    resolution: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> post: PostUniform;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var post_sampler: sampler;
+// Blurred bloom or the colour grading LUT.
+@group(0) @binding(3) var extra: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle covering the screen, uv has 0 at the top like textures.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(source, post_sampler, in.uv).rgb, 1.0);
+}
+
+// params: threshold, knee
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, post_sampler, in.uv).rgb;
+    let threshold = post.params.x;
+    let knee = max(post.params.y, 0.0001);
+    // Soft knee, so cells just under the threshold fade in instead of popping.
+    let brightness = max(color.r, max(color.g, color.b));
+    let soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    let weight = max(soft * soft / (4.0 * knee), brightness - threshold) / max(brightness, 0.0001);
+    return vec4<f32>(color * weight, 1.0);
+}
+
+// params: direction x, direction y, radius in texels
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    // 9 tap gaussian folded into 5 linear samples.
+    var offsets = array<f32, 3>(0.0, 1.3846153846, 3.2307692308);
+    var weights = array<f32, 3>(0.2270270270, 0.3162162162, 0.0702702703);
+    let step = post.params.xy * post.texel_size * post.params.z;
+    var color = textureSample(source, post_sampler, in.uv).rgb * weights[0];
+    for (var i = 1; i < 3; i++) {
+        color += textureSample(source, post_sampler, in.uv + step * offsets[i]).rgb * weights[i];
+        color += textureSample(source, post_sampler, in.uv - step * offsets[i]).rgb * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// params: intensity
+@fragment
+fn fs_bloom(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, post_sampler, in.uv).rgb;
+    let bloom = textureSample(extra, post_sampler, in.uv).rgb;
+    return vec4<f32>(color + bloom * post.params.x, 1.0);
+}
+
+// params: exposure
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let x = textureSample(source, post_sampler, in.uv).rgb * post.params.x;
+    // Narkowicz's fit of the ACES filmic curve.
+    let mapped = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    return vec4<f32>(clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+
+// params: strength, LUT size
+//
+// The LUT is a strip of `size` slices of `size` x `size`, blue picks the
+// slice, red goes across it and green down.
+@fragment
+fn fs_color_grade(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = clamp(textureSample(source, post_sampler, in.uv).rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let size = post.params.y;
+    let blue = color.b * (size - 1.0);
+    let slice = floor(blue);
+    let texel = vec2<f32>(1.0 / (size * size), 1.0 / size);
+    let inner = vec2<f32>(color.r, color.g) * (size - 1.0) + 0.5;
+    let low = (vec2<f32>(slice * size, 0.0) + inner) * texel;
+    let high = (vec2<f32>(min(slice + 1.0, size - 1.0) * size, 0.0) + inner) * texel;
+    let graded = mix(
+        textureSample(extra, post_sampler, low).rgb,
+        textureSample(extra, post_sampler, high).rgb,
+        blue - slice,
+    );
+    return vec4<f32>(mix(color, graded, post.params.x), 1.0);
+}
+
+// params: strength, radius, softness
+@fragment
+fn fs_vignette(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, post_sampler, in.uv).rgb;
+    // 1 in the corners.
+    let distance = length(in.uv - 0.5) * 1.41421356;
+    let shade = smoothstep(post.params.y, post.params.y + post.params.z, distance);
+    return vec4<f32>(color * (1.0 - post.params.x * shade), 1.0);
+}
+
+// params: scanlines, curvature, pixel size
+@fragment
+fn fs_crt(in: VertexOutput) -> @location(0) vec4<f32> {
+    var centred = in.uv * 2.0 - 1.0;
+    centred *= 1.0 + post.params.y * dot(centred.yx, centred.yx);
+    var uv = centred * 0.5 + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let pixel = max(post.params.z, 1.0);
+    uv = (floor(uv * post.resolution / pixel) + 0.5) * pixel / post.resolution;
+    // Sampled after the early return, so no implicit derivatives.
+    let color = textureSampleLevel(source, post_sampler, uv, 0.0).rgb;
+    // Every other row of the screen is a dark scanline.
+    let line = 0.5 + 0.5 * cos(floor(in.clip_position.y) * 3.14159265);
+    return vec4<f32>(color * (1.0 - post.params.x * line), 1.0);
+}
@@ -20,7 +20,7 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return pallete.values[in.color_index];
+    return pallete[in.color_index];
 }
 
 struct CameraUniform {
@@ -31,11 +31,8 @@ struct CameraUniform {
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
-struct Pallete {
-  values: array<vec4<f32>, 16>,
-};
-
-@group(0) @binding(1) var<uniform> pallete: Pallete;
+// One colour per material, as long as the asset list.
+@group(0) @binding(1) var<storage, read> pallete: array<vec4<f32>>;
 
 struct InstanceInput {
     @location(5) position: vec2<f32>,
@@ -1,32 +1,94 @@
+use log::{info, warn};
 use std::sync::Arc;
 use winit::{
     application::ApplicationHandler,
+    dpi::PhysicalSize,
     event::*,
     event_loop::ActiveEventLoop,
     keyboard::{KeyCode, PhysicalKey},
-    window::Window,
+    window::{Fullscreen, Window},
 };
 
-use crate::app_state::State;
+use crate::{app_state::State, engine_config::EngineConfig};
 
-#[derive(Default)]
 pub struct App<'a> {
     window: Option<Arc<Window>>,
     state: Option<State<'a>>,
+    config: EngineConfig,
+    /// Why the app stopped, when it didn't stop because it was closed.
+    pub error: Option<String>,
+}
+
+impl App<'_> {
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            window: None,
+            state: None,
+            config,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: String) {
+        self.error = Some(error);
+        event_loop.exit();
+    }
+
+    /// Ticks and draws one frame, dealing with surface errors and a lost
+    /// device along the way.
+    fn redraw(&mut self, event_loop: &ActiveEventLoop) {
+        let (Some(window), Some(mut state)) = (self.window.clone(), self.state.take()) else {
+            return;
+        };
+
+        if state.is_device_lost() {
+            state = match pollster::block_on(state.recreate(window.clone())) {
+                Ok(state) => {
+                    info!("graphics device recreated");
+                    state
+                }
+                Err(e) => return self.fail(event_loop, e),
+            };
+        }
+
+        state.tick();
+        let result = state.render();
+        if let Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) = result {
+            warn!("surface lost or outdated, reconfiguring it");
+            state.reconfigure_surface();
+        }
+        self.state = Some(state);
+        match result {
+            Err(wgpu::SurfaceError::Timeout) => warn!("surface timed out, skipping frame"),
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                return self.fail(event_loop, "out of GPU memory".to_string());
+            }
+            _ => {}
+        }
+        window.request_redraw();
+    }
 }
 
 impl ApplicationHandler for App<'_> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if self.window.is_none() {
-            let window = Arc::new(
-                event_loop
-                    .create_window(Window::default_attributes())
-                    .unwrap(),
-            );
+            let window_config = &self.config.window;
+            let mut attributes = Window::default_attributes()
+                .with_title(window_config.title.clone())
+                .with_inner_size(PhysicalSize::new(window_config.width, window_config.height));
+            if window_config.fullscreen {
+                attributes = attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+            }
+            let window = match event_loop.create_window(attributes) {
+                Ok(window) => Arc::new(window),
+                Err(e) => return self.fail(event_loop, format!("couldn't create a window: {}", e)),
+            };
             self.window = Some(window.clone());
 
-            let state = pollster::block_on(State::new(window.clone()));
-            self.state = Some(state);
+            match pollster::block_on(State::new(window.clone(), self.config.clone())) {
+                Ok(state) => self.state = Some(state),
+                Err(e) => self.fail(event_loop, e),
+            }
         }
     }
 
@@ -50,17 +112,15 @@ impl ApplicationHandler for App<'_> {
                 ..
             } => event_loop.exit(),
             WindowEvent::Resized(physical_size) => {
-                state.unwrap().resize(physical_size);
-            }
-            WindowEvent::RedrawRequested => {
-                let state = state.unwrap();
-                state.update(0f32);
-                state.render();
-                self.window.as_ref().unwrap().request_redraw();
+                if let Some(state) = state {
+                    state.resize(physical_size);
+                }
             }
+            WindowEvent::RedrawRequested => self.redraw(event_loop),
             event => {
                 if let Some(state) = state {
-                    state.input(&event, 0.0);
+                    let delta_t = state.timestep.frame_secs();
+                    state.input(&event, delta_t);
                 }
             }
         }
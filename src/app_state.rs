@@ -1,28 +1,29 @@
-use core::panic;
-use ecolor::Rgba;
-use glam::{Mat4, Vec2, Vec4};
-use log::info;
-use std::{fs, sync::Arc, time::Instant};
-use wgpu::{
-    util::DeviceExt, BindGroup, BindGroupLayout, Buffer, ComputePipeline,
-    ComputePipelineDescriptor, Origin3d, RenderPipeline, Texture, TextureDescriptor, TextureFormat,
-    TextureUsages,
+use glam::Vec2;
+use log::{error, info, warn};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
 };
+use wgpu::TextureFormat;
 use winit::{
     dpi::PhysicalSize,
     event::{MouseScrollDelta, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
     window::Window,
 };
 
 use crate::{
-    camera::{Camera, CameraUniform},
-    constants::{INDICES, VERTICES},
+    camera::Camera,
+    capture::FrameCapture,
+    engine_config::EngineConfig,
     enums::cell_assets::import_assets,
-    instance_data::{InstanceData, Palette},
+    input::{ActionEvent, ActionMap, InputState, CAMERA_CONTEXT, ENGINE_CONTEXT},
+    renderer::{self, Renderer},
+    time::FixedTimestep,
     world::World,
 };
-use crate::{constants::Vertex, enums::cell_assets::CellAssets};
+
+/// Camera pan speed in world units per second, multiplied by the zoom factor.
+const CAMERA_PAN_SPEED: f32 = 30.0;
 
 pub struct State<'a> {
     pub instance: wgpu::Instance,
@@ -31,265 +32,35 @@ pub struct State<'a> {
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub compute_pipeline: wgpu::ComputePipeline,
-    pub compute_bind_group: wgpu::BindGroup,
-    pub compute_bind_layout: wgpu::BindGroupLayout,
-    pub compute_texture: Texture,
-    pub vertex_buffer: Buffer,
-    pub index_buffer: Buffer,
-    pub num_vertices: u32,
-    pub num_indices: u32,
+    pub renderer: Renderer,
     pub world: World,
     pub camera: Camera,
-    pub instances: Vec<InstanceData>,
-    pub instance_buffer: Buffer,
-    pub instance_buffer_len: usize,
     pub mouse_position: Vec2,
-    pub colors_buffer: Buffer,
-    pub camera_bind_group: BindGroup,
+    pub capture: FrameCapture,
+    pub engine_config: EngineConfig,
+    pub timestep: FixedTimestep,
+    pub actions: ActionMap,
+    pub input: InputState,
+    device_lost: Arc<AtomicBool>,
 }
 
 impl<'a> State<'a> {
-    fn create_screen_sized_texture(size: PhysicalSize<u32>, device: &wgpu::Device) -> Texture {
-        device.create_texture(&TextureDescriptor {
-            label: Some("screen sized texture"),
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::RENDER_ATTACHMENT
-                | TextureUsages::COPY_SRC
-                | TextureUsages::STORAGE_BINDING,
-            view_formats: &[TextureFormat::Rgba8Unorm],
-        })
-    }
-
-    fn init_compute_pipeline(
-        device: &wgpu::Device,
-        size: PhysicalSize<u32>,
-    ) -> (ComputePipeline, BindGroup, BindGroupLayout, Texture) {
-        let compute_shader_file = {
-            match fs::read_to_string("assets/shaders/compute_render.wgsl") {
-                Ok(str) => str,
-                Err(_) => {
-                    panic!("could't load shader at path: assets/shaders/compute_render.wgsl ")
-                }
-            }
-        };
-        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Sand compute shader"),
-            source: wgpu::ShaderSource::Wgsl(compute_shader_file.into()),
-        });
-
-        let compute_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None, // Not an array
-                }],
-                label: Some("out_texture_bind_group_layout"),
-            });
-        let texture = State::create_screen_sized_texture(size, device);
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view),
-            }],
-            label: Some("out_texture_bind_group"),
-        });
-
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Compute sand renderer layout"),
-                bind_group_layouts: &[&compute_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let compute_pipeline_desc = ComputePipelineDescriptor {
-            label: Some("Compute sand render"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: "main",
-            compilation_options: Default::default(),
-            cache: Default::default(),
-        };
-
-        (
-            device.create_compute_pipeline(&compute_pipeline_desc),
-            compute_bind_group,
-            compute_bind_group_layout,
-            texture,
-        )
-    }
-
-    fn load_assets(device: &wgpu::Device) -> (CellAssets, Buffer) {
-        let assets = import_assets().unwrap();
-        let mut palette = Palette {
-            values: [Rgba::RED; 16],
-        };
-        let assets_clone = assets.clone();
-        for i in 0..16 {
-            if let Some(color) = assets_clone.assets_color_vec.get(i) {
-                palette.values[i] = *color;
-            }
-        }
-
-        let colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Colors Buffer"),
-            contents: bytemuck::cast_slice(&[palette]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        (assets.clone(), colors_buffer)
-    }
-
-    fn init_render_pipeline(
-        device: &wgpu::Device,
-        config: wgpu::SurfaceConfiguration,
-        colors_buffer: &Buffer,
-    ) -> (RenderPipeline, BindGroup, Buffer) {
-        let shader_file = {
-            match fs::read_to_string("assets/shaders/shader.wgsl") {
-                Ok(str) => str,
-                Err(_) => {
-                    panic!("could't load shader at path: assets/shaders/shader.wgsl ")
-                }
-            }
-        };
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(shader_file.into()),
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("camera_bind_group_layout"),
-            });
-
-        let camera_uniform = CameraUniform {
-            view_proj: Mat4::ZERO.to_cols_array_2d(),
-            position: Vec4::ZERO,
-        };
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: colors_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("camera_colors_bind_group"),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main", // 1.
-                buffers: &[Vertex::desc(), InstanceData::desc()],
-                compilation_options: Default::default(), // 2.
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None,
-            cache: Default::default(), // 5.
-        });
-
-        (render_pipeline, camera_bind_group, camera_buffer)
+    // Creating some of the wgpu types requires async code
+    pub async fn new(window: Arc<Window>, engine_config: EngineConfig) -> Result<Self, String> {
+        State::with_world(window, engine_config, None).await
     }
 
-    // Creating some of the wgpu types requires async code
-    pub async fn new(window: Arc<Window>) -> Self {
+    /// Sets up the GPU for `window`, creating a new world unless one is given.
+    async fn with_world(
+        window: Arc<Window>,
+        engine_config: EngineConfig,
+        world: Option<World>,
+    ) -> Result<Self, String> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
+            backends: renderer::backends(),
             ..Default::default()
         });
 
@@ -297,34 +68,40 @@ impl<'a> State<'a> {
         //
         // The surface needs to live as long as the window that created it.
         // State owns the window, so this should be safe.
-        let surface = instance.create_surface(Arc::clone(&window)).unwrap();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let surface = instance
+            .create_surface(Arc::clone(&window))
+            .map_err(|e| format!("couldn't create a surface for the window: {}", e))?;
+        let adapter = renderer::request_adapter(
+            &instance,
+            Some(&surface),
+            engine_config.force_fallback_adapter,
+        )
+        .await?;
         info!("{:?}", adapter.get_info());
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web, we'll have to disable some.
-                    required_limits: wgpu::Limits::default(),
-
-                    label: None,
-                    memory_hints: Default::default(),
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = renderer::request_device(&adapter).await?;
+
+        // Flagged for `App` to rebuild the state, dropping the device also
+        // calls this so only real losses count.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            if matches!(
+                reason,
+                wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::DeviceInvalid
+            ) {
+                error!("graphics device lost: {:?} {}", reason, message);
+                lost.store(true, Ordering::Relaxed);
+            }
+        });
 
         let surface_caps = surface.get_capabilities(&adapter);
+        let Some(&alpha_mode) = surface_caps.alpha_modes.first() else {
+            return Err(format!(
+                "graphics adapter {} can't draw to this window",
+                adapter.get_info().name
+            ));
+        };
         // Shader code in this tutorial assumes an sRGB surface texture. Using a different
         // one will result in all the colors coming out darker. If you want to support non
         // sRGB surfaces, you'll need to account for that when drawing to the frame.
@@ -335,99 +112,128 @@ impl<'a> State<'a> {
         //     .copied()
         //     .find(|f| f.is_srgb())
         //     .unwrap_or(surface_caps.formats[0]);
+        let present_mode = match engine_config.present_mode.into() {
+            mode @ (wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync) => mode,
+            mode if surface_caps.present_modes.contains(&mode) => mode,
+            mode => {
+                warn!(
+                    "present mode {:?} isn't supported by this surface, supported: {:?}, using Fifo",
+                    mode, surface_caps.present_modes
+                );
+                wgpu::PresentMode::Fifo
+            }
+        };
+        // Captured frames are copied to the surface where it allows that,
+        // see `render`.
+        let usage = match surface_caps.usages.contains(wgpu::TextureUsages::COPY_DST) {
+            true => wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
+            false => {
+                info!("surface can't be copied to, captured frames are rendered twice");
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            }
+        };
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
+            usage,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode,
+            alpha_mode,
             view_formats: vec![],
             desired_maximum_frame_latency: Default::default(),
         };
         surface.configure(&device, &config);
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
-        let num_vertices = VERTICES.len() as u32;
-        let num_indices = INDICES.len() as u32;
-
-        let (assets, colors_buffer) = State::load_assets(&device);
-
-        let (render_pipeline, camera_bind_group, camera_buffer) =
-            State::init_render_pipeline(&device, config.clone(), &colors_buffer);
-
-        let (compute_pipeline, compute_bind_group, compute_bind_layout, compute_texture) =
-            State::init_compute_pipeline(&device, size);
-
-        let world = World::init_world(assets.clone());
-
-        let camera = Camera::create_camera_from_screen_size(
-            size.width as f32,
-            size.height as f32,
-            0.1,
-            100.0,
-            0.0,
-            Vec2::ZERO,
-            camera_buffer,
+        let assets = import_assets(&engine_config.asset_root).ok_or_else(|| {
+            format!(
+                "couldn't load the cell assets in {}",
+                engine_config.asset_root.display()
+            )
+        })?;
+        let (renderer, camera) = Renderer::new(
+            &device,
+            &queue,
+            config.format,
+            size.width,
+            size.height,
+            &assets,
+            &engine_config,
         );
 
-        let instances = {
-            let mut instances = vec![];
-            for x in -10..10 {
-                for y in -10..10 {
-                    instances.push(InstanceData {
-                        position: Vec2::new(x as f32, y as f32),
-                        color: 0,
-                    })
-                }
-            }
-            instances
+        let world = match world {
+            Some(world) => world,
+            None => World::init_world(assets.clone(), &engine_config)?,
         };
 
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instances),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST, // Add COPY_DST
-        });
-
-        let instance_buffer_len = instances.len();
-
-        Self {
+        Ok(Self {
             instance,
             surface,
             device,
             queue,
             config,
             size,
-            render_pipeline,
-            compute_pipeline,
-            compute_bind_group,
-            compute_bind_layout,
-            compute_texture,
-            vertex_buffer,
-            index_buffer,
-            num_vertices,
-            num_indices,
+            renderer,
             world,
             camera,
-            instances,
-            instance_buffer,
-            instance_buffer_len,
             mouse_position: Vec2::ZERO,
-            colors_buffer,
-            camera_bind_group,
-        }
+            capture: FrameCapture::new(engine_config.capture.clone()),
+            timestep: FixedTimestep::new(engine_config.tick_rate),
+            actions: ActionMap::load_or_default(engine_config.asset_path("input.toml")),
+            input: InputState::default(),
+            engine_config,
+            device_lost,
+        })
+    }
+
+    /// Whether the GPU device was lost and the state has to be rebuilt with
+    /// `recreate`.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Sets everything on the GPU up again for `window`, keeping the world,
+    /// camera, bindings and toggles. The old surface and device are dropped
+    /// first, a window can only have one surface at a time.
+    pub async fn recreate(self, window: Arc<Window>) -> Result<State<'a>, String> {
+        let State {
+            instance,
+            surface,
+            device,
+            queue,
+            renderer,
+            world,
+            camera,
+            mut capture,
+            mut engine_config,
+            timestep,
+            actions,
+            input,
+            ..
+        } = self;
+        engine_config.post_process = renderer.post_process.config().clone();
+        let (position, zoom_factor) = (camera.position, camera.zoom_factor);
+        capture.forget_target();
+        drop(camera);
+        drop(renderer);
+        drop(surface);
+        drop(queue);
+        drop(device);
+        drop(instance);
+
+        let mut state = State::with_world(window, engine_config, Some(world)).await?;
+        state.camera.position = position;
+        state.camera.zoom_factor = zoom_factor;
+        state.camera.update_matrix();
+        state.timestep = timestep;
+        state.actions = actions;
+        state.input = input;
+        state.capture = capture;
+        Ok(state)
+    }
+
+    /// Configures the surface again after it was lost or went out of date.
+    pub fn reconfigure_surface(&mut self) {
+        self.surface.configure(&self.device, &self.config);
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
@@ -436,200 +242,154 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.renderer
+                .resize(&self.device, new_size.width, new_size.height);
             self.camera
                 .update_matrix_from_screen_size(self.size.width as f32, self.size.height as f32);
             self.camera.update_camera_buffer(&self.queue);
-            self.compute_texture = State::create_screen_sized_texture(new_size, &self.device)
         }
     }
 
-    pub fn update_instance_buffer(&mut self) {
-        let mut game_objects = vec![];
-
-        for obj in self.world.storage.iter() {
-            let now = Instant::now();
-            game_objects.append(&mut obj.render());
-            let elapsed_time = now.elapsed();
-            info!(
-                "Running render() took {} seconds for {}.",
-                elapsed_time.as_secs_f32(),
-                obj.get_name(),
-            );
+    fn engine_action(&mut self, action: &ActionEvent) {
+        if !action.just_pressed() {
+            return;
         }
-
-        let now = Instant::now();
-
-        let instance_data_size = std::mem::size_of::<InstanceData>();
-
-        let instances_num = self.instances.len() + game_objects.len();
-
-        if instances_num > self.instance_buffer_len {
-            let new_size = instance_data_size * instances_num;
-
-            self.instance_buffer.destroy();
-            self.instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("Instance Buffer"),
-                size: new_size as u64,
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST, // Add STORAGE if needed
-                mapped_at_creation: false,
-            });
-            self.instance_buffer_len = instances_num;
+        let clock = &mut self.world.clock;
+        match action.action.as_str() {
+            "toggle_pause" => clock.toggle_pause(),
+            "step" => clock.step(1),
+            "slower" => clock.set_time_scale(clock.time_scale() / 2.0),
+            "faster" => clock.set_time_scale(clock.time_scale() * 2.0),
+            "reset_speed" => clock.set_time_scale(1.0),
+            "toggle_lighting" => {
+                let lighting = &mut self.engine_config.lighting;
+                lighting.enabled = !lighting.enabled;
+                info!("lighting enabled: {}", lighting.enabled);
+            }
+            "screenshot" => self.capture.request_screenshot(None),
+            "toggle_recording" => self.capture.toggle_recording(),
+            "toggle_post_process" => self.renderer.post_process.toggle(),
+            action if action.starts_with("toggle_post_") => {
+                let effect = action.trim_start_matches("toggle_post_");
+                if !self.renderer.post_process.toggle_effect(effect) {
+                    warn!("no {} pass in the post-process chain", effect);
+                }
+            }
+            _ => {}
         }
-
-        game_objects.append(&mut self.instances);
-
-        self.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&game_objects),
-        );
-        let elapsed_time = now.elapsed();
-        info!(
-            "Running update_instance_buffer() took {}, cells rendered {}.",
-            elapsed_time.as_secs_f32(),
-            game_objects.len()
-        );
     }
 
-    pub fn update_colors_buffer(&mut self) {}
-
     pub fn input(&mut self, event: &WindowEvent, delta_t: f32) -> bool {
+        self.input.handle_event(event);
         self.world.input(delta_t, event, self.mouse_position);
 
-        if let WindowEvent::MouseWheel { delta, .. } = event {
-            if let MouseScrollDelta::LineDelta(_, scrolled) = delta {
-                info!("scrolled: {}", scrolled);
+        if let WindowEvent::MouseWheel {
+            delta: MouseScrollDelta::LineDelta(_, scrolled),
+            ..
+        } = event
+        {
+            info!("scrolled: {}", scrolled);
 
-                if scrolled > &0.0 {
-                    self.camera.zoom_factor -= 0.7;
-                } else {
-                    self.camera.zoom_factor += 0.7;
-                }
-                self.camera.update_matrix();
-                self.camera.update_camera_buffer(&self.queue);
+            if scrolled > &0.0 {
+                self.camera.zoom_factor -= 0.7;
+            } else {
+                self.camera.zoom_factor += 0.7;
             }
+            self.camera.update_matrix();
+            self.camera.update_camera_buffer(&self.queue);
         }
 
-        if let WindowEvent::KeyboardInput { event, .. } = event {
-            let mut direction = Vec2::ZERO;
-            match event.physical_key {
-                PhysicalKey::Code(KeyCode::KeyW) => {
-                    direction.y = 1.0;
-                }
-                PhysicalKey::Code(KeyCode::KeyA) => {
-                    direction.x = -1.0;
-                }
-                PhysicalKey::Code(KeyCode::KeyS) => {
-                    direction.y = -1.0;
-                }
-                PhysicalKey::Code(KeyCode::KeyD) => {
-                    direction.x = 1.0;
-                }
-                _ => {}
-            }
-            if direction != Vec2::ZERO {
-                self.camera.position += direction.normalize() * self.camera.zoom_factor;
-                self.camera.update_matrix();
-
-                info!("{}", self.camera.position);
-                self.camera.update_camera_buffer(&self.queue);
+        for action in self.actions.translate(event) {
+            self.input.handle_action(&action);
+            match action.context.as_str() {
+                ENGINE_CONTEXT => self.engine_action(&action),
+                CAMERA_CONTEXT => {}
+                _ => self.world.action(&action, self.mouse_position),
             }
         }
 
-        if let WindowEvent::CursorMoved { position, .. } = event {
-            let position_in_game = self
-                .camera
-                .mouse_to_world(Vec2::new(position.x as f32, position.y as f32), self.size);
-
-            self.mouse_position = position_in_game;
-
-            info!("mouse pos in game: {}", position_in_game)
-        }
         false
     }
 
-    pub fn update(&mut self, delta_t: f32) {
-        for obj in self.world.storage.iter_mut() {
-            obj.as_mut().update(delta_t)
+    /// Runs the simulation ticks owed for the time since the last frame.
+    pub fn tick(&mut self) {
+        let mut frame_time = self.timestep.measure_frame();
+        if self.capture.is_recording() {
+            frame_time = self.capture.config.frame_time();
         }
+
+        let pan = self.input.action_axis(
+            CAMERA_CONTEXT,
+            ["pan_left", "pan_right", "pan_down", "pan_up"],
+        );
+        if pan != Vec2::ZERO {
+            self.camera.position += pan.normalize()
+                * CAMERA_PAN_SPEED
+                * self.camera.zoom_factor
+                * frame_time.as_secs_f32();
+            self.camera.update_matrix();
+        }
+
+        self.mouse_position = self
+            .camera
+            .mouse_to_world(self.input.cursor_position, self.size);
+        self.input.cursor_world = self.mouse_position;
+        self.input.frame_time = frame_time.as_secs_f32();
+
+        self.world.set_focus(self.camera.world_position());
+        self.world.set_view(self.camera.visible_rect());
+        self.world.poll_input(&self.input);
+        self.world.run_frame(&mut self.timestep, frame_time);
+        self.input.end_frame();
     }
 
+    /// Draws the frame to the surface. Captured frames are drawn into the
+    /// capture target and copied to the surface, or drawn a second time on
+    /// surfaces that can't be copied to.
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.camera.update_camera_buffer(&self.queue);
-        self.update_instance_buffer();
         let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    }),
-                ],
-                depth_stencil_attachment: None,
-                ..Default::default()
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instance_buffer_len as _);
+        let capturing = self.capture.wants_frame();
+        let copy_to_surface = self.config.usage.contains(wgpu::TextureUsages::COPY_DST);
+        if capturing {
+            let view = self
+                .capture
+                .target(
+                    &self.device,
+                    self.config.format,
+                    self.config.width,
+                    self.config.height,
+                )
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            self.render_to(&view);
         }
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("compute render"),
-                ..Default::default()
-            });
-
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &self.compute_bind_group, &[])
+        if !capturing || !copy_to_surface {
+            let view = output
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            self.render_to(&view);
+        }
+        if capturing {
+            self.capture.finish_frame(
+                &self.device,
+                &self.queue,
+                copy_to_surface.then_some(&output.texture),
+            );
         }
-
-        encoder.copy_texture_to_texture(
-            wgpu::ImageCopyTextureBase {
-                texture: &self.compute_texture,
-                mip_level: 0,
-                origin: Origin3d { x: 0, y: 0, z: 0 },
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyTexture {
-                texture: &output.texture,
-                mip_level: 0,
-                origin: Origin3d::default(),
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::Extent3d {
-                width: 0,
-                height: 0,
-                depth_or_array_layers: 1,
-            },
-        );
-
-        self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
         Ok(())
     }
+
+    fn render_to(&mut self, view: &wgpu::TextureView) {
+        self.renderer.render(
+            &self.device,
+            &self.queue,
+            &mut self.world,
+            &mut self.camera,
+            self.timestep.alpha(),
+            &self.engine_config.lighting,
+            view,
+            [self.config.width as f32, self.config.height as f32],
+        );
+    }
 }
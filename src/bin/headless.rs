@@ -0,0 +1,219 @@
+use std::{path::PathBuf, process::ExitCode, time::Instant};
+
+use clap::Parser;
+use game_engine_wgpu::{
+    engine_config::{CliArgs, EngineConfig},
+    enums::{cell_assets::import_assets, CHUNK_SIZE},
+    offscreen::OffscreenRenderer,
+    world::{World, WorldObject},
+};
+use glam::{IVec2, UVec2};
+
+/// Runs the cell simulation without a window, and without a GPU unless a
+/// frame is rendered.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Number of physics ticks to run.
+    #[arg(long, default_value_t = 600)]
+    ticks: u32,
+
+    /// Engine config file, defaults to `engine.toml` if it exists.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Folder containing `config.toml` and the cell assets.
+    #[arg(long)]
+    assets: Option<PathBuf>,
+
+    /// Start from a save instead of the generator.
+    #[arg(long)]
+    save: Option<PathBuf>,
+
+    /// Override the generator seed.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Log filter, e.g. `info` or `game_engine_wgpu=debug`.
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// Chunk coordinate to stream and simulate around.
+    #[arg(long, num_args = 2, value_names = ["X", "Y"], allow_negative_numbers = true)]
+    focus: Option<Vec<i32>>,
+
+    /// Write the final state to a save file.
+    #[arg(long)]
+    save_to: Option<PathBuf>,
+
+    /// Export the simulated area around the focus to a PNG.
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Render the final frame around the focus with the full renderer into
+    /// a PNG, using a software adapter when there's no GPU.
+    #[arg(long)]
+    render: Option<PathBuf>,
+
+    /// Size in pixels of the rendered frame.
+    #[arg(long, num_args = 2, value_names = ["WIDTH", "HEIGHT"], default_values_t = [1280, 720])]
+    render_size: Vec<u32>,
+
+    /// Render with a software adapter even when a GPU is available.
+    #[arg(long)]
+    fallback_adapter: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let config = match EngineConfig::from_args(&CliArgs {
+        config: args.config.clone(),
+        assets: args.assets.clone(),
+        seed: args.seed,
+        save: args.save.clone(),
+        log_level: args.log_level.clone(),
+        fallback_adapter: args.fallback_adapter,
+        ..Default::default()
+    }) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(errors) = config.validate() {
+        for e in errors {
+            eprintln!("error: {e}");
+        }
+        return ExitCode::from(2);
+    }
+    config.init_logger();
+
+    let Some(assets) = import_assets(&config.asset_root) else {
+        eprintln!(
+            "couldn't load assets from {}",
+            config.asset_path("config.toml").display()
+        );
+        return ExitCode::FAILURE;
+    };
+
+    let mut world = match World::init_cell_world(&assets, &config) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let focus_chunk = match args.focus.as_deref() {
+        Some([x, y]) => IVec2::new(*x, *y),
+        _ => IVec2::ZERO,
+    };
+    let focus = (focus_chunk * CHUNK_SIZE).as_vec2() + CHUNK_SIZE.as_vec2() / 2.0;
+    world.set_focus(focus);
+
+    let delta_t = 1.0 / config.tick_rate;
+    let start = Instant::now();
+    let mut slowest = 0f32;
+    for _ in 0..args.ticks {
+        let tick_start = Instant::now();
+        world.update(delta_t);
+        slowest = slowest.max(tick_start.elapsed().as_secs_f32());
+    }
+    let total = start.elapsed().as_secs_f32();
+
+    println!("ticks:        {}", args.ticks);
+    println!("total time:   {:.3}s", total);
+    println!(
+        "tick time:    avg {:.3}ms, max {:.3}ms",
+        total * 1000.0 / args.ticks.max(1) as f32,
+        slowest * 1000.0
+    );
+    println!(
+        "chunks:       {} awake, {} sleeping, {} unloaded",
+        world.chunks.len(),
+        world.compressed_chunks.len(),
+        world.unloaded_chunks.len()
+    );
+
+    let counts = match world.cell_counts() {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("couldn't count cells: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort();
+    println!(
+        "cells:        {}",
+        counts.iter().map(|(_, n)| n).sum::<usize>()
+    );
+    for (cell, count) in counts {
+        let name = assets
+            .get(cell - 1)
+            .map(|asset| asset.name)
+            .unwrap_or_else(|| format!("unknown {}", cell));
+        println!("  {:<12}{}", name, count);
+    }
+
+    match world.state_hash() {
+        Ok(hash) => println!("state hash:   {:016x}", hash),
+        Err(e) => {
+            eprintln!("couldn't hash world: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(path) = &args.save_to {
+        if let Err(e) = world.save(path) {
+            eprintln!("couldn't save to {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(path) = &args.export {
+        let radius = world.streaming.simulation_radius;
+        let min = (focus_chunk - IVec2::splat(radius)) * CHUNK_SIZE;
+        let size = (UVec2::splat(radius as u32 * 2 + 1)) * CHUNK_SIZE.as_uvec2();
+        if let Err(e) = world.export_png(path, min, size) {
+            eprintln!("couldn't export to {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(path) = &args.render {
+        let [width, height] = args.render_size[..] else {
+            unreachable!("clap takes exactly two sizes")
+        };
+        let mut offscreen =
+            match pollster::block_on(OffscreenRenderer::new(config, &assets, width, height)) {
+                Ok(offscreen) => offscreen,
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+        println!(
+            "adapter:      {} ({:?}, {:?})",
+            offscreen.adapter_info.name,
+            offscreen.adapter_info.device_type,
+            offscreen.adapter_info.backend
+        );
+        // The rendered area spans the simulated chunks.
+        let radius = world.streaming.simulation_radius;
+        offscreen.look_at(focus, ((radius * 2 + 1) * CHUNK_SIZE.y) as f32);
+        let mut scene = World {
+            assets,
+            ..Default::default()
+        };
+        scene.add_obj(Box::new(world));
+        if let Err(e) = offscreen.render_png(&mut scene, path) {
+            eprintln!("couldn't render to {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -22,6 +22,7 @@ pub struct CameraUniform {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         right: f32,
         left: f32,
@@ -123,6 +124,30 @@ impl Camera {
         }
     }
 
+    /// Centre of the view in world coordinates, matching the offset the
+    /// shader applies from `CameraUniform::position`.
+    pub fn world_position(&self) -> Vec2 {
+        if self.zoom_factor == 0.0 {
+            self.position
+        } else {
+            self.position / self.zoom_factor
+        }
+    }
+
+    /// Smallest and largest world position on screen, from the same bounds
+    /// `create_matrix` projects.
+    pub fn visible_rect(&self) -> (Vec2, Vec2) {
+        let zoom_factor = if self.zoom_factor == 0.0 {
+            1.0
+        } else {
+            self.zoom_factor
+        };
+        let center = self.world_position();
+        let min = Vec2::new(self.left, self.bottom) * (1.0 + zoom_factor);
+        let max = Vec2::new(self.right, self.top) * (1.0 + zoom_factor);
+        (center + min.min(max), center + min.max(max))
+    }
+
     pub fn get_matrix(&self) -> Mat4 {
         self.create_matrix()
     }
@@ -0,0 +1,409 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::image::RgbaImage;
+
+/// Frames a recording can fall behind its writer before rendering waits.
+const RECORDING_QUEUE: usize = 8;
+
+/// Captures started by this process, tells apart captures taken in the same
+/// millisecond whose files haven't been written yet.
+static CAPTURE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    /// Numbered PNG frames in a folder, for `ffmpeg -i frame_%05d.png`.
+    #[default]
+    Png,
+    /// One looping animated GIF.
+    Gif,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Folder screenshots and recordings are written to.
+    pub dir: PathBuf,
+    /// Frames per second of recordings. While recording, every frame moves
+    /// the simulation on by exactly `1 / fps` seconds.
+    pub fps: f32,
+    pub format: RecordingFormat,
+    /// Quantizer speed of GIF frames from 1 (best) to 30 (fastest).
+    pub gif_speed: i32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("captures"),
+            fps: 30.0,
+            format: RecordingFormat::default(),
+            gif_speed: 10,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn frame_time(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.fps)
+    }
+
+    /// A path in `dir` that no earlier capture used, named after the time
+    /// and a running count.
+    fn new_path(&self, prefix: &str, extension: &str) -> PathBuf {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        loop {
+            let count = CAPTURE_COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = self.dir.join(format!("{}_{}_{}", prefix, millis, count));
+            let path = match extension {
+                "" => path,
+                extension => path.with_extension(extension),
+            };
+            if !path.exists() {
+                return path;
+            }
+        }
+    }
+}
+
+/// Copies `texture` into a buffer and waits for the GPU to map it. The
+/// texture needs `COPY_SRC` usage and a 4 byte per pixel RGBA format.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<RgbaImage, String> {
+    let (width, height) = (texture.width(), texture.height());
+    let row_bytes = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_row_bytes = row_bytes.div_ceil(align) * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_row_bytes * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("couldn't read the frame back: {}", e))?;
+
+    let pixels = {
+        let data = slice.get_mapped_range();
+        data.chunks(padded_row_bytes as usize)
+            .flat_map(|row| bytemuck::cast_slice::<u8, [u8; 4]>(&row[..row_bytes as usize]))
+            .copied()
+            .collect()
+    };
+    buffer.unmap();
+    Ok(RgbaImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Frames being written to disk on a background thread.
+pub struct Recording {
+    pub path: PathBuf,
+    pub frames: u32,
+    sender: SyncSender<RgbaImage>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl Recording {
+    pub fn start(config: &CaptureConfig) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(RECORDING_QUEUE);
+        let (path, writer) = match config.format {
+            RecordingFormat::Png => {
+                let path = config.new_path("recording", "");
+                fs::create_dir_all(&path)?;
+                let dir = path.clone();
+                let writer = thread::spawn(move || write_png_frames(&dir, receiver));
+                (path, writer)
+            }
+            RecordingFormat::Gif => {
+                let path = config.new_path("recording", "gif");
+                fs::create_dir_all(&config.dir)?;
+                let file = io::BufWriter::new(fs::File::create(&path)?);
+                // GIF delays are in hundredths of a second.
+                let delay = (100.0 / config.fps).round().max(1.0) as u16;
+                let speed = config.gif_speed.clamp(1, 30);
+                let writer = thread::spawn(move || write_gif(file, delay, speed, receiver));
+                (path, writer)
+            }
+        };
+        Ok(Self {
+            path,
+            frames: 0,
+            sender,
+            writer,
+        })
+    }
+
+    /// Queues a frame, waiting when the writer is behind.
+    pub fn push(&mut self, frame: RgbaImage) {
+        if self.sender.send(frame).is_ok() {
+            self.frames += 1;
+        }
+    }
+
+    /// Waits for the queued frames to be written.
+    pub fn finish(self) -> io::Result<u32> {
+        drop(self.sender);
+        self.writer
+            .join()
+            .map_err(|_| io::Error::other("recording writer panicked"))??;
+        Ok(self.frames)
+    }
+}
+
+fn write_png_frames(dir: &Path, receiver: Receiver<RgbaImage>) -> io::Result<()> {
+    for (index, frame) in receiver.into_iter().enumerate() {
+        frame.save_png(dir.join(format!("frame_{:05}.png", index)))?;
+    }
+    Ok(())
+}
+
+fn write_gif(
+    file: io::BufWriter<fs::File>,
+    delay: u16,
+    speed: i32,
+    receiver: Receiver<RgbaImage>,
+) -> io::Result<()> {
+    let mut frames = receiver.into_iter();
+    let Some(first) = frames.next() else {
+        return Ok(());
+    };
+    let (width, height) = gif_size(&first)?;
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+    for frame in std::iter::once(first).chain(frames) {
+        if gif_size(&frame)? != (width, height) {
+            warn!(
+                "skipping {}x{} frame in {}x{} gif",
+                frame.width, frame.height, width, height
+            );
+            continue;
+        }
+        let mut pixels: Vec<u8> = bytemuck::cast_slice(&frame.pixels).to_vec();
+        let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, speed);
+        gif_frame.delay = delay;
+        encoder.write_frame(&gif_frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn gif_size(frame: &RgbaImage) -> io::Result<(u16, u16)> {
+    match (u16::try_from(frame.width), u16::try_from(frame.height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(io::Error::other(format!(
+            "{}x{} is too big for a gif",
+            frame.width, frame.height
+        ))),
+    }
+}
+
+/// Screenshots and recordings of the window. Frames are captured by
+/// rendering into `target` and copying it to the surface, so surfaces that
+/// can't be read from work too.
+pub struct FrameCapture {
+    pub config: CaptureConfig,
+    screenshot: Option<PathBuf>,
+    recording: Option<Recording>,
+    target: Option<wgpu::Texture>,
+    /// Screenshots still being saved.
+    writers: Vec<JoinHandle<()>>,
+}
+
+impl FrameCapture {
+    pub fn new(config: CaptureConfig) -> Self {
+        Self {
+            config,
+            screenshot: None,
+            recording: None,
+            target: None,
+            writers: vec![],
+        }
+    }
+
+    /// Saves the next rendered frame to `path`, or to a new file in the
+    /// capture folder.
+    pub fn request_screenshot(&mut self, path: Option<PathBuf>) {
+        self.screenshot = Some(path.unwrap_or_else(|| self.config.new_path("screenshot", "png")));
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start_recording(&mut self) {
+        if self.is_recording() {
+            return;
+        }
+        match Recording::start(&self.config) {
+            Ok(recording) => {
+                info!("recording to {}", recording.path.display());
+                self.recording = Some(recording);
+            }
+            Err(e) => error!("couldn't start recording: {}", e),
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        let path = recording.path.clone();
+        match recording.finish() {
+            Ok(frames) => info!("recorded {} frames to {}", frames, path.display()),
+            Err(e) => error!("couldn't write recording {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn toggle_recording(&mut self) {
+        if self.is_recording() {
+            self.stop_recording();
+        } else {
+            self.start_recording();
+        }
+    }
+
+    /// Whether the next frame has to be rendered into `target`.
+    pub fn wants_frame(&self) -> bool {
+        self.screenshot.is_some() || self.recording.is_some()
+    }
+
+    /// Texture to render a captured frame into, matching the surface.
+    pub fn target(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> &wgpu::Texture {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        if let Some(target) = &self.target {
+            if target.size() != size || target.format() != format {
+                target.destroy();
+                self.target = None;
+            }
+        }
+        self.target.get_or_insert_with(|| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Capture Target"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        })
+    }
+
+    /// Drops the target, which belongs to the device it was made on.
+    pub fn forget_target(&mut self) {
+        self.target = None;
+    }
+
+    /// Copies the frame rendered into `target` to `output`, if given, and
+    /// hands it to the pending screenshot and the recording.
+    pub fn finish_frame(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output: Option<&wgpu::Texture>,
+    ) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        if let Some(output) = output {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+            encoder.copy_texture_to_texture(
+                target.as_image_copy(),
+                output.as_image_copy(),
+                target.size(),
+            );
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        let frame = match read_texture(device, queue, target) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!("couldn't capture frame: {}", e);
+                self.screenshot = None;
+                return;
+            }
+        };
+        if let Some(path) = self.screenshot.take() {
+            let frame = frame.clone();
+            self.writers.retain(|writer| !writer.is_finished());
+            self.writers
+                .push(thread::spawn(move || match frame.save_png(&path) {
+                    Ok(()) => info!("saved screenshot to {}", path.display()),
+                    Err(e) => error!("couldn't save screenshot to {}: {}", path.display(), e),
+                }));
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.push(frame);
+        }
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        self.stop_recording();
+        for writer in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use std::{fs, path::Path};
+
+use bytemuck::{Pod, Zeroable};
+use ecolor::Rgba;
+use glam::{IVec2, Vec2};
+use hashbrown::{HashMap, HashSet};
+use log::{info, warn};
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, RenderPipeline};
+
+use crate::{
+    constants::Vertex,
+    enums::{cell_assets::CellAssets, CHUNK_SIZE, CHUNK_SIZE_LEN},
+    world::World,
+};
+
+/// Chunk slots the material buffer starts with, it doubles when full.
+const INITIAL_SLOTS: usize = 64;
+
+/// Bytes one chunk takes up in the material buffer.
+const SLOT_BYTES: u64 = (CHUNK_SIZE_LEN * std::mem::size_of::<u32>()) as u64;
+
+/// World objects made of chunks of cells, drawn by `ChunkRenderer` as one
+/// quad per chunk instead of one instance per cell.
+pub trait ChunkSource {
+    /// Chunks that should be on screen this frame.
+    fn visible_chunks(&mut self) -> Vec<IVec2>;
+
+    /// Chunks left out of the last `visible_chunks` for being off screen.
+    fn culled_chunks(&self) -> usize {
+        0
+    }
+
+    /// Chunks whose cells changed since the last call.
+    fn take_dirty_chunks(&mut self) -> Vec<IVec2>;
+
+    /// Every cell in `Chunk` index order, packed with `pack_cell`.
+    fn chunk_materials(&self, chunk_pos: IVec2) -> Option<Vec<u32>>;
+
+    /// Like `chunk_materials` but only the material ids, without shades.
+    fn chunk_ids(&self, chunk_pos: IVec2) -> Option<Vec<u32>> {
+        self.chunk_materials(chunk_pos).map(|cells| {
+            cells
+                .into_iter()
+                .map(|cell| cell & ((1 << MATERIAL_BITS) - 1))
+                .collect()
+        })
+    }
+
+    /// Height from which column `x` is open sky unless edited, like the
+    /// ground surface of a generated world. `None` when unknown, the sky
+    /// then starts above whatever is lit.
+    fn sky_height(&self, _x: i32) -> Option<i32> {
+        None
+    }
+}
+
+/// Bits of a packed cell holding the material, the rest is the shade.
+pub const MATERIAL_BITS: u32 = 24;
+
+/// Packs a material id, 0 for empty and asset index plus one otherwise,
+/// with the cell's colour shade for the chunk shader.
+pub fn pack_cell(material: u32, shade: u8) -> u32 {
+    (material & ((1 << MATERIAL_BITS) - 1)) | (shade as u32) << MATERIAL_BITS
+}
+
+/// Appearance of one material as the chunk shader reads it.
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+struct GpuMaterial {
+    color: [f32; 4],
+    gradient: [f32; 4],
+    variation: f32,
+    texture_offset: u32,
+    texture_width: u32,
+    texture_height: u32,
+}
+
+fn rgba_array(color: Rgba) -> [f32; 4] {
+    [color.r(), color.g(), color.b(), color.a()]
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct ChunkInstance {
+    origin: Vec2,
+    slot: u32,
+}
+
+impl ChunkInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![5 => Float32x2, 6 => Uint32];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Keeps the materials of visible chunks in a storage buffer on the GPU,
+/// one slot per chunk, and draws each chunk as a single quad.
+///
+/// Chunks are only uploaded when they come into view or their source marks
+/// them dirty.
+pub struct ChunkRenderer {
+    pipeline: RenderPipeline,
+    cells_layout: BindGroupLayout,
+    cells_buffer: Buffer,
+    cells_bind_group: BindGroup,
+    /// Appearance of every material, indexed by asset id.
+    materials: Vec<GpuMaterial>,
+    /// Texture pixels of every material, packed RGBA8.
+    texels: Vec<u32>,
+    materials_buffer: Buffer,
+    texels_buffer: Buffer,
+    /// Slot of every uploaded chunk, keyed by world object index and chunk.
+    slots: HashMap<(usize, IVec2), u32>,
+    free_slots: Vec<u32>,
+    capacity: usize,
+    instance_buffer: Buffer,
+    instance_capacity: usize,
+    instance_count: u32,
+    /// Chunks the sources culled last update, for debugging.
+    pub culled_chunks: usize,
+    /// Chunks of any source whose cells changed before the last update.
+    pub changed_chunks: HashSet<IVec2>,
+}
+
+impl ChunkRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_layout: &BindGroupLayout,
+        asset_root: &Path,
+    ) -> Self {
+        let shader_path = asset_root.join("shaders/chunk.wgsl");
+        let shader_file = match fs::read_to_string(&shader_path) {
+            Ok(str) => str,
+            Err(_) => panic!("could't load shader at path: {} ", shader_path.display()),
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Chunk shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_file.into()),
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cells_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[storage_entry(0), storage_entry(1), storage_entry(2)],
+            label: Some("chunk_cells_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Chunk Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &cells_layout],
+            push_constant_ranges: &[],
+        });
+
+        let constants = std::collections::HashMap::from([
+            ("chunk_width".to_string(), CHUNK_SIZE.x as f64),
+            ("chunk_height".to_string(), CHUNK_SIZE.y as f64),
+        ]);
+        let compilation_options = wgpu::PipelineCompilationOptions {
+            constants: &constants,
+            ..Default::default()
+        };
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Chunk Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), ChunkInstance::desc()],
+                compilation_options: compilation_options.clone(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: Default::default(),
+        });
+
+        let cells_buffer = ChunkRenderer::create_cells_buffer(device, INITIAL_SLOTS);
+        let (materials_buffer, texels_buffer) =
+            ChunkRenderer::create_material_buffers(device, &[GpuMaterial::zeroed()], &[0]);
+        let cells_bind_group = ChunkRenderer::create_cells_bind_group(
+            device,
+            &cells_layout,
+            &cells_buffer,
+            &materials_buffer,
+            &texels_buffer,
+        );
+        let instance_buffer = ChunkRenderer::create_instance_buffer(device, INITIAL_SLOTS);
+
+        Self {
+            pipeline,
+            cells_layout,
+            cells_buffer,
+            cells_bind_group,
+            materials: vec![],
+            texels: vec![],
+            materials_buffer,
+            texels_buffer,
+            slots: HashMap::new(),
+            free_slots: (0..INITIAL_SLOTS as u32).rev().collect(),
+            capacity: INITIAL_SLOTS,
+            instance_buffer,
+            instance_capacity: INITIAL_SLOTS,
+            instance_count: 0,
+            culled_chunks: 0,
+            changed_chunks: HashSet::new(),
+        }
+    }
+
+    fn create_cells_buffer(device: &wgpu::Device, slots: usize) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Chunk Cells Buffer"),
+            size: SLOT_BYTES * slots as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_material_buffers(
+        device: &wgpu::Device,
+        materials: &[GpuMaterial],
+        texels: &[u32],
+    ) -> (Buffer, Buffer) {
+        let materials = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Chunk Materials Buffer"),
+            contents: bytemuck::cast_slice(materials),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let texels = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Chunk Texels Buffer"),
+            contents: bytemuck::cast_slice(texels),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        (materials, texels)
+    }
+
+    fn create_cells_bind_group(
+        device: &wgpu::Device,
+        layout: &BindGroupLayout,
+        cells: &Buffer,
+        materials: &Buffer,
+        texels: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: cells.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: materials.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: texels.as_entire_binding(),
+                },
+            ],
+            label: Some("chunk_cells_bind_group"),
+        })
+    }
+
+    fn rebuild_bind_group(&mut self, device: &wgpu::Device) {
+        self.cells_bind_group = ChunkRenderer::create_cells_bind_group(
+            device,
+            &self.cells_layout,
+            &self.cells_buffer,
+            &self.materials_buffer,
+            &self.texels_buffer,
+        );
+    }
+
+    /// Rebuilds the material table when the colours, gradients or
+    /// textures of `assets` changed.
+    pub fn update_materials(&mut self, device: &wgpu::Device, assets: &CellAssets) {
+        let mut materials = vec![];
+        let mut texels = vec![];
+        for asset in assets.assets.iter() {
+            let mut material = GpuMaterial {
+                color: rgba_array(asset.color),
+                gradient: rgba_array(asset.gradient.unwrap_or(asset.color)),
+                variation: asset.variation,
+                ..GpuMaterial::zeroed()
+            };
+            if let Some(image) = &asset.texture_image {
+                material.texture_offset = texels.len() as u32;
+                material.texture_width = image.width;
+                material.texture_height = image.height;
+                texels.extend(image.pixels.iter().map(|pixel| u32::from_le_bytes(*pixel)));
+            }
+            materials.push(material);
+        }
+        if materials == self.materials && texels == self.texels {
+            return;
+        }
+        info!(
+            "chunk materials updated, {} materials, {} texels",
+            materials.len(),
+            texels.len()
+        );
+
+        self.materials = materials;
+        self.texels = texels;
+
+        // Buffers can't be empty.
+        let fallback_material = [GpuMaterial::zeroed()];
+        let materials = match self.materials.is_empty() {
+            true => &fallback_material[..],
+            false => &self.materials,
+        };
+        let texels = match self.texels.is_empty() {
+            true => &[0][..],
+            false => &self.texels,
+        };
+        (self.materials_buffer, self.texels_buffer) =
+            ChunkRenderer::create_material_buffers(device, materials, texels);
+        self.rebuild_bind_group(device);
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, instances: usize) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Chunk Instance Buffer"),
+            size: (std::mem::size_of::<ChunkInstance>() * instances) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Most chunks the material buffer can hold on this device.
+    fn max_slots(device: &wgpu::Device) -> usize {
+        let limits = device.limits();
+        let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        (max_bytes / SLOT_BYTES) as usize
+    }
+
+    /// Makes room for `needed` chunks. Growing drops every uploaded chunk,
+    /// they are uploaded again into the new buffer.
+    fn reserve(&mut self, device: &wgpu::Device, needed: usize) {
+        if needed <= self.capacity {
+            return;
+        }
+        let capacity = needed
+            .next_power_of_two()
+            .max(self.capacity * 2)
+            .min(ChunkRenderer::max_slots(device));
+        if capacity <= self.capacity {
+            return;
+        }
+        info!(
+            "chunk buffer grew from {} to {} slots",
+            self.capacity, capacity
+        );
+
+        self.cells_buffer.destroy();
+        self.cells_buffer = ChunkRenderer::create_cells_buffer(device, capacity);
+        self.rebuild_bind_group(device);
+        self.capacity = capacity;
+        self.slots.clear();
+        self.free_slots = (0..capacity as u32).rev().collect();
+    }
+
+    /// Uploads chunks that came into view or changed, frees the slots of
+    /// chunks that left the view and rebuilds the per-chunk instances.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, world: &mut World) {
+        let mut sources: Vec<(usize, &mut dyn ChunkSource)> = world
+            .storage
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, object)| object.chunk_source().map(|source| (index, source)))
+            .collect();
+
+        let frame: Vec<(Vec<IVec2>, HashSet<IVec2>)> = sources
+            .iter_mut()
+            .map(|(_, source)| {
+                let dirty = source.take_dirty_chunks().into_iter().collect();
+                (source.visible_chunks(), dirty)
+            })
+            .collect();
+        self.changed_chunks = frame
+            .iter()
+            .flat_map(|(_, dirty)| dirty.iter().copied())
+            .collect();
+        self.culled_chunks = sources
+            .iter()
+            .map(|(_, source)| source.culled_chunks())
+            .sum();
+
+        let visible: HashSet<(usize, IVec2)> = sources
+            .iter()
+            .zip(frame.iter())
+            .flat_map(|((index, _), (chunks, _))| chunks.iter().map(|pos| (*index, *pos)))
+            .collect();
+
+        self.reserve(device, visible.len());
+
+        let free_slots = &mut self.free_slots;
+        self.slots.retain(|key, slot| {
+            let keep = visible.contains(key);
+            if !keep {
+                free_slots.push(*slot);
+            }
+            keep
+        });
+
+        let mut instances = vec![];
+        let mut uploaded = 0;
+        for ((index, source), (chunks, dirty)) in sources.iter().zip(frame.iter()) {
+            for chunk_pos in chunks {
+                let key = (*index, *chunk_pos);
+                let slot = match self.slots.get(&key) {
+                    Some(slot) if !dirty.contains(chunk_pos) => {
+                        instances.push(ChunkInstance {
+                            origin: (*chunk_pos * CHUNK_SIZE).as_vec2(),
+                            slot: *slot,
+                        });
+                        continue;
+                    }
+                    Some(slot) => *slot,
+                    None => match self.free_slots.pop() {
+                        Some(slot) => slot,
+                        None => {
+                            warn!("no chunk slots left, {} not drawn", chunk_pos);
+                            continue;
+                        }
+                    },
+                };
+                let Some(materials) = source.chunk_materials(*chunk_pos) else {
+                    self.free_slots.push(slot);
+                    self.slots.remove(&key);
+                    continue;
+                };
+                queue.write_buffer(
+                    &self.cells_buffer,
+                    slot as u64 * SLOT_BYTES,
+                    bytemuck::cast_slice(&materials),
+                );
+                self.slots.insert(key, slot);
+                uploaded += 1;
+                instances.push(ChunkInstance {
+                    origin: (*chunk_pos * CHUNK_SIZE).as_vec2(),
+                    slot,
+                });
+            }
+        }
+
+        if instances.len() > self.instance_capacity {
+            self.instance_buffer.destroy();
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instance_buffer =
+                ChunkRenderer::create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        self.instance_count = instances.len() as u32;
+
+        if uploaded > 0 {
+            info!(
+                "uploaded {} chunks, {} of {} slots in use",
+                uploaded,
+                self.slots.len(),
+                self.capacity
+            );
+        }
+    }
+
+    /// Draws every visible chunk. Expects the shared quad vertex and index
+    /// buffers and the camera bind group used by the instance pipeline.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        vertex_buffer: &'a Buffer,
+        index_buffer: &'a Buffer,
+        num_indices: u32,
+    ) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.cells_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..num_indices, 0, 0..self.instance_count);
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::{
+    capture::CaptureConfig,
+    lighting::LightingConfig,
+    objects::sand::streaming::StreamingConfig,
+    post_process::{PostEffect, PostProcessConfig},
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "engine.toml";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum, PartialEq, Eq)]
+pub enum PresentMode {
+    #[default]
+    AutoVsync,
+    AutoNoVsync,
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "game_engine_wgpu".to_string(),
+            width: 1280,
+            height: 720,
+            fullscreen: false,
+        }
+    }
+}
+
+/// Engine settings, read from `engine.toml` and overridden by command line flags.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub asset_root: PathBuf,
+    pub window: WindowConfig,
+    pub present_mode: PresentMode,
+    /// Skips the GPU and renders with a software adapter.
+    pub force_fallback_adapter: bool,
+    /// Overrides the seed of the world generator.
+    pub seed: Option<u64>,
+    pub start_save: Option<PathBuf>,
+    /// `env_logger` filter, `RUST_LOG` is used when unset.
+    pub log_level: Option<String>,
+    /// Simulation ticks per second.
+    pub tick_rate: f32,
+    pub streaming: StreamingConfig,
+    pub lighting: LightingConfig,
+    pub post_process: PostProcessConfig,
+    pub capture: CaptureConfig,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            asset_root: PathBuf::from("assets"),
+            window: WindowConfig::default(),
+            present_mode: PresentMode::default(),
+            force_fallback_adapter: false,
+            seed: None,
+            start_save: None,
+            log_level: None,
+            tick_rate: 60.0,
+            streaming: StreamingConfig::default(),
+            lighting: LightingConfig::default(),
+            post_process: PostProcessConfig::default(),
+            capture: CaptureConfig::default(),
+        }
+    }
+}
+
+#[derive(Parser, Debug, Default)]
+#[command(version, about)]
+pub struct CliArgs {
+    /// Engine config file, defaults to `engine.toml` if it exists.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Folder containing `config.toml`, cells and shaders.
+    #[arg(long)]
+    pub assets: Option<PathBuf>,
+
+    #[arg(long)]
+    pub width: Option<u32>,
+
+    #[arg(long)]
+    pub height: Option<u32>,
+
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    #[arg(long, value_enum)]
+    pub present_mode: Option<PresentMode>,
+
+    /// Render with a software adapter even when a GPU is available.
+    #[arg(long)]
+    pub fallback_adapter: bool,
+
+    /// Seed for the world generator.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Save file to load on startup.
+    #[arg(long)]
+    pub save: Option<PathBuf>,
+
+    /// Log filter, e.g. `info` or `game_engine_wgpu=debug`.
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Simulation ticks per second.
+    #[arg(long)]
+    pub tick_rate: Option<f32>,
+}
+
+impl EngineConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let contents = fs::read_to_string(path.as_ref()).map_err(|e| {
+            format!(
+                "couldn't read engine config {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("invalid engine config {}: {}", path.as_ref().display(), e))
+    }
+
+    /// Loads the config file named by `--config`, or `engine.toml` if present,
+    /// then applies the remaining flags on top.
+    pub fn from_args(args: &CliArgs) -> Result<Self, String> {
+        let mut config = match &args.config {
+            Some(path) => EngineConfig::load(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                EngineConfig::load(DEFAULT_CONFIG_PATH)?
+            }
+            None => EngineConfig::default(),
+        };
+
+        if let Some(assets) = &args.assets {
+            config.asset_root = assets.clone();
+        }
+        if let Some(width) = args.width {
+            config.window.width = width;
+        }
+        if let Some(height) = args.height {
+            config.window.height = height;
+        }
+        if args.fullscreen {
+            config.window.fullscreen = true;
+        }
+        if let Some(present_mode) = args.present_mode {
+            config.present_mode = present_mode;
+        }
+        if args.fallback_adapter {
+            config.force_fallback_adapter = true;
+        }
+        if let Some(seed) = args.seed {
+            config.seed = Some(seed);
+        }
+        if let Some(save) = &args.save {
+            config.start_save = Some(save.clone());
+        }
+        if let Some(log_level) = &args.log_level {
+            config.log_level = Some(log_level.clone());
+        }
+        if let Some(tick_rate) = args.tick_rate {
+            config.tick_rate = tick_rate;
+        }
+
+        Ok(config)
+    }
+
+    /// Checks everything that can be checked before a window exists and
+    /// returns one message per problem.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if !self.asset_root.is_dir() {
+            errors.push(format!(
+                "asset root {} is not a directory, pass --assets <DIR>",
+                self.asset_root.display()
+            ));
+        } else if !self.asset_root.join("config.toml").is_file() {
+            errors.push(format!(
+                "asset root {} has no config.toml",
+                self.asset_root.display()
+            ));
+        }
+
+        if self.window.width == 0 || self.window.height == 0 {
+            errors.push(format!(
+                "window size {}x{} must be at least 1x1",
+                self.window.width, self.window.height
+            ));
+        }
+
+        if !(self.tick_rate.is_finite() && self.tick_rate > 0.0 && self.tick_rate <= 1000.0) {
+            errors.push(format!(
+                "tick rate {} must be between 0 and 1000 ticks per second",
+                self.tick_rate
+            ));
+        }
+
+        if let Some(save) = &self.start_save {
+            if !save.is_file() {
+                errors.push(format!("save file {} doesn't exist", save.display()));
+            }
+        }
+
+        if let Some(log_level) = &self.log_level {
+            let directives = log_level.split('/').next().unwrap_or_default();
+            for directive in directives.split(',').filter(|d| !d.is_empty()) {
+                if let Some((_, level)) = directive.split_once('=') {
+                    if level.parse::<log::LevelFilter>().is_err() {
+                        errors.push(format!(
+                            "invalid log level {:?} in {:?}, expected one of off, error, warn, info, debug, trace",
+                            level, log_level
+                        ));
+                    }
+                }
+            }
+        }
+
+        let streaming = &self.streaming;
+        if streaming.simulation_radius < 0
+            || streaming.simulation_radius > streaming.render_radius
+            || streaming.render_radius >= streaming.unload_radius
+        {
+            errors.push(format!(
+                "streaming radii must satisfy 0 <= simulation ({}) <= render ({}) < unload ({})",
+                streaming.simulation_radius, streaming.render_radius, streaming.unload_radius
+            ));
+        }
+
+        let lighting = &self.lighting;
+        if !(0.0..1.0).contains(&lighting.falloff) || lighting.margin < 0 {
+            errors.push(format!(
+                "lighting falloff {} must be between 0 and 1 and margin {} at least 0",
+                lighting.falloff, lighting.margin
+            ));
+        }
+
+        for pass in &self.post_process.passes {
+            if let PostEffect::ColorGrade(grade) = &pass.effect {
+                if pass.enabled && !self.asset_root.join(&grade.lut).is_file() {
+                    errors.push(format!(
+                        "colour grading LUT {} doesn't exist in {}",
+                        grade.lut,
+                        self.asset_root.display()
+                    ));
+                }
+            }
+        }
+
+        if !(self.capture.fps.is_finite() && self.capture.fps > 0.0 && self.capture.fps <= 100.0) {
+            errors.push(format!(
+                "capture fps {} must be between 0 and 100 frames per second",
+                self.capture.fps
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn asset_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.asset_root.join(path)
+    }
+
+    pub fn init_logger(&self) {
+        let mut builder =
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("error"));
+        if let Some(log_level) = &self.log_level {
+            builder.parse_filters(log_level);
+        }
+        builder.init();
+    }
+}
@@ -16,3 +16,6 @@ pub const CELL_SIZE: Vec3 = Vec3::new(10.0, 10.0, 10.0);
 pub const CHUNK_SIZE: IVec2 = IVec2::new(100, 100);
 
 pub const CHUNK_SIZE_LEN: usize = (CHUNK_SIZE.x * CHUNK_SIZE.y) as usize;
+
+/// Physics ticks without any cell changes before a chunk is put to sleep and compressed.
+pub const CHUNK_SLEEP_TICKS: u32 = 600;
@@ -4,7 +4,8 @@ use log::{info, warn};
 use serde::*;
 
 use super::CellPhysicsType;
-use std::fs;
+use crate::image::RgbaImage;
+use std::{fs, path::Path};
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct CellAsset {
@@ -12,6 +13,28 @@ pub struct CellAsset {
     pub color: Rgba,
     pub name: String,
     pub density: i32,
+    /// Brightness jitter per cell, 0.1 gives shades within 10% of `color`.
+    #[serde(default)]
+    pub variation: f32,
+    /// Each cell gets a fixed shade between `color` and this colour.
+    #[serde(default)]
+    pub gradient: Option<Rgba>,
+    /// PNG relative to the assets folder, tiled one pixel per cell by world
+    /// position and multiplied with the cell colour.
+    #[serde(default)]
+    pub texture: Option<String>,
+    #[serde(skip)]
+    pub texture_image: Option<RgbaImage>,
+    /// Light given off by the cell, lava or fire glow with this colour.
+    #[serde(default)]
+    pub emissive: Option<Rgba>,
+    /// Brightness of `emissive`, 1.0 is as bright as daylight.
+    #[serde(default = "default_emissive_intensity")]
+    pub emissive_intensity: f32,
+}
+
+fn default_emissive_intensity() -> f32 {
+    1.0
 }
 
 #[derive(Default, Clone, Deserialize, Debug)]
@@ -25,6 +48,9 @@ pub struct CellAssets {
 #[derive(Debug, Deserialize)]
 pub struct ConfigAsset {
     pub cell_paths: Vec<String>,
+    /// World generator config, relative to the assets folder.
+    #[serde(default)]
+    pub generator: Option<String>,
 }
 
 impl CellAssets {
@@ -59,6 +85,24 @@ impl CellAssets {
         None
     }
 
+    /// Index of the asset whose colour is nearest to `color`, ignoring alpha.
+    pub fn closest_by_color(&self, color: Rgba) -> Option<usize> {
+        let distance = |other: &Rgba| {
+            let (r, g, b) = (
+                other.r() - color.r(),
+                other.g() - color.g(),
+                other.b() - color.b(),
+            );
+            r * r + g * g + b * b
+        };
+
+        self.assets_color_vec
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .map(|(index, _)| index)
+    }
+
     pub fn add(&mut self, asset: CellAsset) {
         self.assets.push(asset.clone());
         self.assets_color_vec.push(asset.color);
@@ -75,31 +119,37 @@ impl CellAssets {
     }
 }
 
-pub fn import_assets() -> Option<CellAssets> {
-    let contents = fs::read_to_string("assets/config.toml");
+pub fn import_config(asset_root: &Path) -> Option<ConfigAsset> {
+    let contents = fs::read_to_string(asset_root.join("config.toml"));
 
     if let Ok(paths_config_str) = contents {
         let config: ConfigAsset = toml::from_str(&paths_config_str).expect("Could't load config");
+        return Some(config);
+    } else {
+        info!("couldn't parse")
+    }
+    None
+}
 
+pub fn import_assets(asset_root: &Path) -> Option<CellAssets> {
+    if let Some(config) = import_config(asset_root) {
         let mut assets = CellAssets::default();
         info!("assets: {:?}", assets);
 
         for path in config.cell_paths {
             info!("trying to start load at path: {}", path);
-            if let Some(asset) = import_asset(path) {
+            if let Some(asset) = import_asset(asset_root, path) {
                 assets.add(asset);
             }
         }
 
         return Some(assets);
-    } else {
-        info!("couldn't parse")
     }
     None
 }
 
-pub fn import_asset(path: String) -> Option<CellAsset> {
-    let contents = fs::read_to_string("assets/".to_string() + &path);
+pub fn import_asset(asset_root: &Path, path: String) -> Option<CellAsset> {
+    let contents = fs::read_to_string(asset_root.join(path));
 
     if let Ok(asset_str) = contents {
         info!("loading asset file");
@@ -110,6 +160,14 @@ pub fn import_asset(path: String) -> Option<CellAsset> {
         match some_asset {
             Ok(mut asset) => {
                 asset.color = crate::utils::normalize_color(asset.color);
+                asset.gradient = asset.gradient.map(crate::utils::normalize_color);
+                asset.emissive = asset.emissive.map(crate::utils::normalize_color);
+                if let Some(texture) = &asset.texture {
+                    match RgbaImage::load_png(asset_root.join(texture)) {
+                        Ok(image) => asset.texture_image = Some(image),
+                        Err(e) => warn!("couldn't load texture {}: {}", texture, e),
+                    }
+                }
                 info!("asset loaded: {:?}", asset);
                 return Some(asset);
             }
@@ -0,0 +1,398 @@
+use std::{fs, path::Path};
+
+use glam::Vec2;
+use hashbrown::{HashMap, HashSet};
+use log::warn;
+use serde::Deserialize;
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+};
+
+/// Bindings used when `input.toml` is missing or invalid.
+pub const DEFAULT_BINDINGS: &str = include_str!("../assets/input.toml");
+
+/// Context handled by `State` itself: pause, step, time scale and lighting.
+pub const ENGINE_CONTEXT: &str = "engine";
+/// Context handled by `State` itself: camera panning.
+pub const CAMERA_CONTEXT: &str = "camera";
+
+/// A key with the modifiers that must be held, or a mouse button.
+///
+/// Written as `"KeyW"`, `"Ctrl+Shift+KeyZ"` or `"Mouse:Left"`. Key names are
+/// winit `KeyCode` variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Key {
+        code: KeyCode,
+        modifiers: ModifiersState,
+    },
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    pub fn parse(binding: &str) -> Result<Self, String> {
+        if let Some(button) = binding.strip_prefix("Mouse:") {
+            let button = match button {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                "Back" => MouseButton::Back,
+                "Forward" => MouseButton::Forward,
+                other => MouseButton::Other(
+                    other
+                        .parse()
+                        .map_err(|_| format!("unknown mouse button {:?}", binding))?,
+                ),
+            };
+            return Ok(Binding::Mouse(button));
+        }
+
+        let mut modifiers = ModifiersState::empty();
+        let mut parts: Vec<&str> = binding.split('+').collect();
+        let key = parts.pop().unwrap_or_default();
+        for modifier in parts {
+            modifiers |= match modifier {
+                "Ctrl" => ModifiersState::CONTROL,
+                "Shift" => ModifiersState::SHIFT,
+                "Alt" => ModifiersState::ALT,
+                "Super" => ModifiersState::SUPER,
+                _ => return Err(format!("unknown modifier {:?} in {:?}", modifier, binding)),
+            };
+        }
+        let code = KeyCode::deserialize(toml::Value::String(key.to_string()))
+            .map_err(|_| format!("unknown key {:?} in {:?}", key, binding))?;
+        Ok(Binding::Key { code, modifiers })
+    }
+
+    /// Whether a press or release of `other` triggers this binding. Releases
+    /// ignore modifiers, so letting go of Ctrl first still ends the action.
+    fn matches(&self, other: &Binding, pressed: bool) -> bool {
+        match (self, other) {
+            (
+                Binding::Key { code, modifiers },
+                Binding::Key {
+                    code: other_code,
+                    modifiers: other_modifiers,
+                },
+            ) => code == other_code && (!pressed || modifiers == other_modifiers),
+            (Binding::Mouse(button), Binding::Mouse(other_button)) => button == other_button,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ActionEvent {
+    pub context: String,
+    pub action: String,
+    pub pressed: bool,
+    /// Set for key repeats while the key is held down.
+    pub repeat: bool,
+}
+
+impl ActionEvent {
+    /// A fresh press, ignoring key repeats.
+    pub fn just_pressed(&self) -> bool {
+        self.pressed && !self.repeat
+    }
+}
+
+#[derive(Debug)]
+pub struct InputContext {
+    pub name: String,
+    pub priority: i32,
+    pub enabled: bool,
+    actions: Vec<(String, Vec<Binding>)>,
+}
+
+impl InputContext {
+    /// Actions in this context bound to `binding`.
+    fn bound_actions<'a>(
+        &'a self,
+        binding: &'a Binding,
+        pressed: bool,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.actions
+            .iter()
+            .filter(move |(_, bindings)| bindings.iter().any(|b| b.matches(binding, pressed)))
+            .map(|(action, _)| action.as_str())
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions
+            .iter()
+            .find(|(name, _)| name == action)
+            .map(|(_, bindings)| bindings.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+struct ContextFile {
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    actions: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct ActionMapFile {
+    contexts: HashMap<String, ContextFile>,
+}
+
+/// Named actions per input context, loaded from `input.toml`:
+///
+/// ```toml
+/// [contexts.player]
+/// priority = 20
+/// actions = { move_up = ["KeyW"], jump = ["Space", "Mouse:Left"] }
+/// ```
+///
+/// A press goes to the enabled context with the highest priority that binds
+/// it, so contexts lower down never see keys claimed above them.
+#[derive(Debug)]
+pub struct ActionMap {
+    contexts: Vec<InputContext>,
+    modifiers: ModifiersState,
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        ActionMap::parse(DEFAULT_BINDINGS).expect("default input bindings are invalid")
+    }
+}
+
+impl ActionMap {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let file: ActionMapFile =
+            toml::from_str(contents).map_err(|e| format!("invalid input bindings: {}", e))?;
+
+        let mut contexts = vec![];
+        for (name, context) in file.contexts {
+            let mut actions = vec![];
+            for (action, bindings) in context.actions {
+                let bindings = bindings
+                    .iter()
+                    .map(|binding| Binding::parse(binding))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("{}.{}: {}", name, action, e))?;
+                actions.push((action, bindings));
+            }
+            actions.sort_by(|a, b| a.0.cmp(&b.0));
+            contexts.push(InputContext {
+                name,
+                priority: context.priority,
+                enabled: true,
+                actions,
+            });
+        }
+        contexts.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.name.cmp(&b.name)));
+
+        Ok(Self {
+            contexts,
+            modifiers: ModifiersState::empty(),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let contents = fs::read_to_string(path.as_ref()).map_err(|e| {
+            format!(
+                "couldn't read input bindings {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        ActionMap::parse(&contents)
+    }
+
+    /// Loads `path`, falling back to the built in bindings.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        match ActionMap::load(path) {
+            Ok(actions) => actions,
+            Err(e) => {
+                warn!("{}, using default bindings", e);
+                ActionMap::default()
+            }
+        }
+    }
+
+    pub fn context(&self, name: &str) -> Option<&InputContext> {
+        self.contexts.iter().find(|context| context.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        match self
+            .contexts
+            .iter_mut()
+            .find(|context| context.name == name)
+        {
+            Some(context) => context.enabled = enabled,
+            None => warn!("no input context named {}", name),
+        }
+    }
+
+    /// Turns a window event into the actions it triggers.
+    pub fn translate(&mut self, event: &WindowEvent) -> Vec<ActionEvent> {
+        let (binding, pressed, repeat) = match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                return vec![];
+            }
+            WindowEvent::KeyboardInput { event, .. } => match event.physical_key {
+                PhysicalKey::Code(code) => (
+                    Binding::Key {
+                        code,
+                        modifiers: self.modifiers,
+                    },
+                    event.state == ElementState::Pressed,
+                    event.repeat,
+                ),
+                PhysicalKey::Unidentified(_) => return vec![],
+            },
+            WindowEvent::MouseInput { state, button, .. } => (
+                Binding::Mouse(*button),
+                *state == ElementState::Pressed,
+                false,
+            ),
+            _ => return vec![],
+        };
+
+        // Releases go to every context, a key held in one context must still
+        // be let go there after a higher priority binding took over.
+        let mut events = vec![];
+        for context in self.contexts.iter().filter(|context| context.enabled) {
+            events.extend(
+                context
+                    .bound_actions(&binding, pressed)
+                    .map(|action| ActionEvent {
+                        context: context.name.clone(),
+                        action: action.to_string(),
+                        pressed,
+                        repeat,
+                    }),
+            );
+            if pressed && !events.is_empty() {
+                break;
+            }
+        }
+        events
+    }
+}
+
+/// Keys, buttons and actions currently held, plus cursor and scroll movement
+/// since the last frame. Objects poll it once per frame in `poll_input`.
+#[derive(Default, Debug)]
+pub struct InputState {
+    keys_down: HashSet<KeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    actions_down: HashSet<(String, String)>,
+    actions_pressed: HashSet<(String, String)>,
+    /// Cursor position in window pixels.
+    pub cursor_position: Vec2,
+    /// Cursor position in world cells.
+    pub cursor_world: Vec2,
+    /// Cursor movement in window pixels this frame.
+    pub cursor_delta: Vec2,
+    /// Scroll wheel lines this frame, positive away from the user.
+    pub scroll_delta: f32,
+    /// Seconds since the previous frame.
+    pub frame_time: f32,
+}
+
+impl InputState {
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    match event.state {
+                        ElementState::Pressed => self.keys_down.insert(code),
+                        ElementState::Released => self.keys_down.remove(&code),
+                    };
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                match state {
+                    ElementState::Pressed => self.buttons_down.insert(*button),
+                    ElementState::Released => self.buttons_down.remove(button),
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = Vec2::new(position.x as f32, position.y as f32);
+                self.cursor_delta += position - self.cursor_position;
+                self.cursor_position = position;
+            }
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(_, lines),
+                ..
+            } => self.scroll_delta += lines,
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::PixelDelta(position),
+                ..
+            } => self.scroll_delta += position.y as f32 / 20.0,
+            // Releases that happen while unfocused never arrive.
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => {}
+        }
+    }
+
+    pub fn handle_action(&mut self, action: &ActionEvent) {
+        let key = (action.context.clone(), action.action.clone());
+        if action.pressed {
+            if !action.repeat {
+                self.actions_pressed.insert(key.clone());
+            }
+            self.actions_down.insert(key);
+        } else {
+            self.actions_down.remove(&key);
+        }
+    }
+
+    pub fn key_down(&self, code: KeyCode) -> bool {
+        self.keys_down.contains(&code)
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    fn contains(set: &HashSet<(String, String)>, context: &str, action: &str) -> bool {
+        set.iter().any(|(c, a)| c == context && a == action)
+    }
+
+    pub fn action_down(&self, context: &str, action: &str) -> bool {
+        InputState::contains(&self.actions_down, context, action)
+    }
+
+    /// Pressed at some point this frame, even if already released again.
+    pub fn action_just_pressed(&self, context: &str, action: &str) -> bool {
+        InputState::contains(&self.actions_pressed, context, action)
+    }
+
+    /// Held now or tapped during this frame.
+    pub fn action_active(&self, context: &str, action: &str) -> bool {
+        self.action_down(context, action) || self.action_just_pressed(context, action)
+    }
+
+    /// -1, 0 or 1 on each axis from four held actions.
+    pub fn action_axis(&self, context: &str, [left, right, down, up]: [&str; 4]) -> Vec2 {
+        let axis = |negative: &str, positive: &str| {
+            self.action_down(context, positive) as i32 as f32
+                - self.action_down(context, negative) as i32 as f32
+        };
+        Vec2::new(axis(left, right), axis(down, up))
+    }
+
+    /// Clears the per-frame deltas, call after every object has polled.
+    pub fn end_frame(&mut self) {
+        self.actions_pressed.clear();
+        self.cursor_delta = Vec2::ZERO;
+        self.scroll_delta = 0.0;
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys_down.clear();
+        self.buttons_down.clear();
+        self.actions_down.clear();
+    }
+}
@@ -0,0 +1,150 @@
+use log::info;
+use wgpu::Buffer;
+
+use crate::instance_data::InstanceData;
+
+/// Smallest slice handed to an object, so objects that grow by a few
+/// instances don't force a relayout every frame.
+const MIN_SLICE_CAPACITY: usize = 16;
+
+/// Slices are laid out again once less than 1 / `SHRINK_RATIO` of them is used.
+const SHRINK_RATIO: usize = 4;
+
+/// Instances of one object and where they live in the shared buffer.
+#[derive(Default)]
+struct InstanceSlice {
+    offset: usize,
+    capacity: usize,
+    revision: Option<u64>,
+    data: Vec<InstanceData>,
+    changed: bool,
+}
+
+/// Shared instance buffer where every object owns a slice, so only the
+/// objects whose instances changed are sent to the GPU.
+///
+/// Slices have some room to grow. When one outgrows it, or most of the
+/// buffer sits unused, every slice is laid out again and re-sent.
+pub struct InstanceBuffer {
+    buffer: Buffer,
+    capacity: usize,
+    slices: Vec<InstanceSlice>,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: InstanceBuffer::create_buffer(device, MIN_SLICE_CAPACITY),
+            capacity: MIN_SLICE_CAPACITY,
+            slices: vec![],
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (std::mem::size_of::<InstanceData>() * capacity) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Whether slice `index` already holds what an object at `revision`
+    /// renders. Always false for objects without a revision.
+    pub fn is_current(&self, index: usize, revision: Option<u64>) -> bool {
+        revision.is_some() && self.slices.get(index).map(|slice| slice.revision) == Some(revision)
+    }
+
+    /// Replaces the instances of slice `index`, it's only re-sent if they differ.
+    pub fn set(&mut self, index: usize, revision: Option<u64>, data: Vec<InstanceData>) {
+        if index >= self.slices.len() {
+            self.slices.resize_with(index + 1, Default::default);
+        }
+        let slice = &mut self.slices[index];
+        slice.revision = revision;
+        if slice.data != data {
+            slice.data = data;
+            slice.changed = true;
+        }
+    }
+
+    /// Drops the slices from `len` on, for objects that went away.
+    pub fn truncate(&mut self, len: usize) {
+        self.slices.truncate(len);
+    }
+
+    /// Instances drawn by `draw`.
+    pub fn len(&self) -> usize {
+        self.slices.iter().map(|slice| slice.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether a slice outgrew its room or uses less than 1 / `SHRINK_RATIO` of it.
+    fn needs_layout(&self) -> bool {
+        self.slices.iter().any(|slice| {
+            let len = slice.data.len();
+            len > slice.capacity
+                || (slice.capacity > MIN_SLICE_CAPACITY && len * SHRINK_RATIO < slice.capacity)
+        })
+    }
+
+    /// Gives every slice a fresh place in a buffer sized for the current
+    /// instances.
+    fn layout(&mut self, device: &wgpu::Device) {
+        let mut offset = 0;
+        for slice in self.slices.iter_mut() {
+            slice.capacity = match slice.data.len() {
+                0 => 0,
+                len => len.next_power_of_two().max(MIN_SLICE_CAPACITY),
+            };
+            slice.offset = offset;
+            slice.changed = true;
+            offset += slice.capacity;
+        }
+        let capacity = offset.max(MIN_SLICE_CAPACITY);
+        if capacity != self.capacity {
+            info!(
+                "instance buffer resized from {} to {} instances",
+                self.capacity, capacity
+            );
+            self.buffer.destroy();
+            self.buffer = InstanceBuffer::create_buffer(device, capacity);
+            self.capacity = capacity;
+        }
+    }
+
+    /// Writes the slices that changed since the last upload. Returns the
+    /// number of instances sent.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> usize {
+        if self.needs_layout() {
+            self.layout(device);
+        }
+        let mut sent = 0;
+        for slice in self.slices.iter_mut().filter(|slice| slice.changed) {
+            slice.changed = false;
+            if slice.data.is_empty() {
+                continue;
+            }
+            queue.write_buffer(
+                &self.buffer,
+                (slice.offset * std::mem::size_of::<InstanceData>()) as u64,
+                bytemuck::cast_slice(&slice.data),
+            );
+            sent += slice.data.len();
+        }
+        sent
+    }
+
+    /// Draws every slice with the pipeline and bind groups already set on
+    /// `render_pass`.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, num_indices: u32) {
+        render_pass.set_vertex_buffer(1, self.buffer.slice(..));
+        for slice in self.slices.iter().filter(|slice| !slice.data.is_empty()) {
+            let start = slice.offset as u32;
+            render_pass.draw_indexed(0..num_indices, 0, start..start + slice.data.len() as u32);
+        }
+    }
+}
@@ -2,7 +2,9 @@ use bytemuck::{Pod, Zeroable};
 use ecolor::Rgba;
 use glam::Vec2;
 
-#[derive(Clone, Copy, Pod, Zeroable)]
+use crate::enums::cell_assets::CellAssets;
+
+#[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
 #[repr(C)]
 pub struct InstanceData {
     pub position: Vec2,
@@ -43,8 +45,24 @@ impl InstanceData {
     }
 }
 
-#[derive(Clone, Copy, Pod, Zeroable)]
-#[repr(C)]
+/// Colour of every material, indexed by asset id. Grows with the assets.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Palette {
-    pub values: [Rgba; 16],
+    pub values: Vec<Rgba>,
+}
+
+impl Palette {
+    /// Falls back to a single red entry when there are no assets, GPU
+    /// buffers can't be empty.
+    pub fn from_assets(assets: &CellAssets) -> Self {
+        let mut values = assets.assets_color_vec.clone();
+        if values.is_empty() {
+            values.push(Rgba::RED);
+        }
+        Self { values }
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        std::mem::size_of_val(self.values.as_slice()) as u64
+    }
 }
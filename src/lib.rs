@@ -0,0 +1,20 @@
+pub mod app;
+pub mod app_state;
+pub mod camera;
+pub mod capture;
+pub mod chunk_renderer;
+pub mod constants;
+pub mod engine_config;
+pub mod enums;
+pub mod image;
+pub mod input;
+pub mod instance_buffer;
+pub mod instance_data;
+pub mod lighting;
+pub mod objects;
+pub mod offscreen;
+pub mod post_process;
+pub mod renderer;
+pub mod time;
+pub mod utils;
+pub mod world;
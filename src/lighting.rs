@@ -0,0 +1,635 @@
+use std::{fs, path::Path};
+
+use bytemuck::{Pod, Zeroable};
+use glam::{IVec2, UVec2, Vec2};
+use hashbrown::HashSet;
+use serde::Deserialize;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, RenderPipeline, Texture};
+
+use crate::{
+    chunk_renderer::ChunkSource,
+    enums::{cell_assets::CellAssets, CellPhysicsType, CHUNK_SIZE},
+    world::World,
+};
+
+/// Share of the light a fluid cell lets through.
+const FLUID_TRANSMISSION: f32 = 0.8;
+
+/// Largest light map side in cells, zoomed far out the rest is lit by the
+/// edge of the map.
+const MAX_LIGHT_SIZE: i32 = 1024;
+
+/// Sweeps over the light map, more let light find its way around more
+/// corners.
+const LIGHT_ROUNDS: usize = 2;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LightingConfig {
+    pub enabled: bool,
+    /// Colour of the sky light coming in from above.
+    pub ambient: [f32; 3],
+    /// Brightness of cells no light reaches.
+    pub min_light: f32,
+    /// Light kept per cell travelled through air.
+    pub falloff: f32,
+    /// How much emitted light brightens the cells and the air around it.
+    pub glow: f32,
+    /// Cells around the view that are lit too, so light from off screen
+    /// reaches into it.
+    pub margin: i32,
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ambient: [1.0, 1.0, 1.0],
+            min_light: 0.06,
+            falloff: 0.93,
+            glow: 0.35,
+            margin: 48,
+        }
+    }
+}
+
+/// How a material lets light through and how much it gives off, index 0 is
+/// empty.
+#[derive(Clone, Copy, Default)]
+struct LightMaterial {
+    transmission: f32,
+    emission: [f32; 3],
+}
+
+impl LightMaterial {
+    fn table(assets: &CellAssets) -> Vec<LightMaterial> {
+        let empty = LightMaterial {
+            transmission: 1.0,
+            emission: [0.0; 3],
+        };
+        std::iter::once(empty)
+            .chain(assets.assets.iter().map(|asset| {
+                LightMaterial {
+                    transmission: match asset.physics_behavior {
+                        CellPhysicsType::Fluid => FLUID_TRANSMISSION,
+                        _ => 0.0,
+                    },
+                    emission: asset
+                        .emissive
+                        .map(|color| {
+                            let intensity = asset.emissive_intensity * color.a();
+                            [color.r(), color.g(), color.b()].map(|c| c * intensity)
+                        })
+                        .unwrap_or_default(),
+                }
+            }))
+            .collect()
+    }
+}
+
+/// Material id of a cell in a chunk, the first source with a cell wins.
+fn cell_id(chunks: &[Vec<u32>], index: usize) -> u32 {
+    chunks
+        .iter()
+        .map(|ids| ids[index])
+        .find(|id| *id != 0)
+        .unwrap_or(0)
+}
+
+/// Light of every cell in a rectangle of the world. Sky light comes down
+/// each column from the ground surface until something blocks it, emissive
+/// cells add coloured light, and both spread through air and fluids but
+/// stop at solids.
+pub struct LightMap {
+    pub origin: IVec2,
+    pub size: UVec2,
+    /// Emitted light in rgb and sky light in a, rows bottom to top.
+    pub light: Vec<[f32; 4]>,
+    /// Row above the highest cell the sky light was traced through, edits
+    /// further up don't change the map.
+    pub sky_top: i32,
+}
+
+impl LightMap {
+    /// Lights the cells of `sources` between `min` and `max`.
+    pub fn compute(
+        sources: &[&dyn ChunkSource],
+        assets: &CellAssets,
+        min: IVec2,
+        max: IVec2,
+        falloff: f32,
+    ) -> LightMap {
+        LightMap::compute_with(sources, &LightMaterial::table(assets), min, max, falloff)
+    }
+
+    fn compute_with(
+        sources: &[&dyn ChunkSource],
+        materials: &[LightMaterial],
+        min: IVec2,
+        max: IVec2,
+        falloff: f32,
+    ) -> LightMap {
+        let size = (max - min + IVec2::ONE).max(IVec2::ONE).as_uvec2();
+        let (width, height) = (size.x as usize, size.y as usize);
+
+        let mut ids = vec![0u32; width * height];
+        let first_chunk = min.div_euclid(CHUNK_SIZE);
+        let last_chunk = (min + size.as_ivec2() - IVec2::ONE).div_euclid(CHUNK_SIZE);
+        for source in sources {
+            for chunk_y in first_chunk.y..=last_chunk.y {
+                for chunk_x in first_chunk.x..=last_chunk.x {
+                    let chunk_pos = IVec2::new(chunk_x, chunk_y);
+                    let Some(cells) = source.chunk_ids(chunk_pos) else {
+                        continue;
+                    };
+                    let chunk_origin = chunk_pos * CHUNK_SIZE;
+                    let from = (min - chunk_origin).max(IVec2::ZERO);
+                    let to = (min + size.as_ivec2() - chunk_origin).min(CHUNK_SIZE);
+                    for y in from.y..to.y {
+                        for x in from.x..to.x {
+                            let id = cells[(y * CHUNK_SIZE.x + x) as usize];
+                            if id != 0 {
+                                let cell = chunk_origin + IVec2::new(x, y) - min;
+                                ids[cell.y as usize * width + cell.x as usize] = id;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let material = |id: u32| materials.get(id as usize).copied().unwrap_or_default();
+        let (sky_above, sky_top) = LightMap::sky_above(sources, materials, min.x, width, max.y + 1);
+        let mut light = vec![[0.0; 4]; width * height];
+        let mut spread = vec![0.0; width * height];
+        for (x, sky_above) in sky_above.into_iter().enumerate() {
+            let mut sky = sky_above;
+            for y in (0..height).rev() {
+                let index = y * width + x;
+                let cell = material(ids[index]);
+                let [r, g, b] = cell.emission;
+                light[index] = [r, g, b, sky];
+                sky *= cell.transmission;
+                // Emissive cells pass light on even when they're solid,
+                // otherwise lava under a crust wouldn't glow.
+                spread[index] = if cell.emission != [0.0; 3] {
+                    falloff
+                } else {
+                    falloff * cell.transmission
+                };
+            }
+        }
+
+        let mut light_map = LightMap {
+            origin: min,
+            size,
+            light,
+            sky_top,
+        };
+        for _ in 0..LIGHT_ROUNDS {
+            light_map.sweep(&spread, false);
+            light_map.sweep(&spread, true);
+        }
+        light_map
+    }
+
+    /// Sky light reaching row `top` in the `width` columns from `left`, and
+    /// the row the tracing started from. Columns whose ground surface, see
+    /// `ChunkSource::sky_height`, is higher up are traced through the cells
+    /// in between, chunks that aren't loaded there are still ground and
+    /// block the sky.
+    fn sky_above(
+        sources: &[&dyn ChunkSource],
+        materials: &[LightMaterial],
+        left: i32,
+        width: usize,
+        top: i32,
+    ) -> (Vec<f32>, i32) {
+        let surfaces: Vec<Option<i32>> = (0..width)
+            .map(|x| {
+                sources
+                    .iter()
+                    .filter_map(|source| source.sky_height(left + x as i32))
+                    .max()
+                    .filter(|surface| *surface > top)
+            })
+            .collect();
+        let mut sky = vec![1.0; width];
+        let Some(ceiling) = surfaces.iter().flatten().max().copied() else {
+            return (sky, top);
+        };
+
+        let first_chunk = IVec2::new(left, top).div_euclid(CHUNK_SIZE);
+        let last_chunk = IVec2::new(left + width as i32 - 1, ceiling - 1).div_euclid(CHUNK_SIZE);
+        for chunk_y in first_chunk.y..=last_chunk.y {
+            for chunk_x in first_chunk.x..=last_chunk.x {
+                let chunk_pos = IVec2::new(chunk_x, chunk_y);
+                let chunk_origin = chunk_pos * CHUNK_SIZE;
+                let chunks: Vec<Vec<u32>> = sources
+                    .iter()
+                    .filter_map(|source| source.chunk_ids(chunk_pos))
+                    .collect();
+                for local_x in 0..CHUNK_SIZE.x {
+                    let Ok(x) = usize::try_from(chunk_origin.x + local_x - left) else {
+                        continue;
+                    };
+                    let Some(Some(surface)) = surfaces.get(x) else {
+                        continue;
+                    };
+                    let from = (top - chunk_origin.y).max(0);
+                    let to = (surface - chunk_origin.y).min(CHUNK_SIZE.y);
+                    if from >= to {
+                        continue;
+                    }
+                    if chunks.is_empty() {
+                        sky[x] = 0.0;
+                        continue;
+                    }
+                    for y in from..to {
+                        let id = cell_id(&chunks, (y * CHUNK_SIZE.x + local_x) as usize);
+                        sky[x] *= materials
+                            .get(id as usize)
+                            .map_or(0.0, |material| material.transmission);
+                    }
+                }
+            }
+        }
+        (sky, ceiling)
+    }
+
+    /// Carries light from the left and below, or from the right and above
+    /// when `reverse`, one row at a time.
+    fn sweep(&mut self, spread: &[f32], reverse: bool) {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        let light = &mut self.light;
+        let mut receive = |index: usize, from: usize| {
+            let carried = light[from].map(|c| c * spread[from]);
+            let cell = &mut light[index];
+            for (c, carried) in cell.iter_mut().zip(carried) {
+                *c = c.max(carried);
+            }
+        };
+        for step in 0..width * height {
+            let index = if reverse {
+                width * height - 1 - step
+            } else {
+                step
+            };
+            let (x, y) = (index % width, index / width);
+            if reverse {
+                if x + 1 < width {
+                    receive(index, index + 1);
+                }
+                if y + 1 < height {
+                    receive(index, index + width);
+                }
+            } else {
+                if x > 0 {
+                    receive(index, index - 1);
+                }
+                if y > 0 {
+                    receive(index, index - width);
+                }
+            }
+        }
+    }
+
+    /// Light as RGBA8 texels, clamped to 0..1.
+    pub fn texels(&self) -> Vec<u8> {
+        self.light
+            .iter()
+            .flat_map(|cell| cell.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct LightUniform {
+    origin: [f32; 2],
+    size: [f32; 2],
+    /// Sky colour in rgb, `min_light` in a.
+    ambient: [f32; 4],
+    glow: f32,
+    _padding: [f32; 3],
+}
+
+/// What the light map on the GPU was computed from.
+#[derive(PartialEq)]
+struct LightKey {
+    min: IVec2,
+    max: IVec2,
+    falloff: f32,
+}
+
+/// Lights the cell layer from a `LightMap` of the view: one pass darkens
+/// what the light doesn't reach, a second adds the glow of emissive cells.
+/// The map is only computed again when the view moves to other chunks or
+/// cells in or above it change.
+pub struct LightRenderer {
+    shade_pipeline: RenderPipeline,
+    glow_pipeline: RenderPipeline,
+    light_layout: BindGroupLayout,
+    light_bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    sampler: wgpu::Sampler,
+    texture: Texture,
+    /// Built from the assets the renderer was created with.
+    materials: Vec<LightMaterial>,
+    key: Option<LightKey>,
+    /// Highest chunk row whose cells the light map depends on.
+    top_chunk: i32,
+}
+
+impl LightRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_layout: &BindGroupLayout,
+        assets: &CellAssets,
+        asset_root: &Path,
+    ) -> Self {
+        let shader_path = asset_root.join("shaders/lighting.wgsl");
+        let shader_file = match fs::read_to_string(&shader_path) {
+            Ok(str) => str,
+            Err(_) => panic!("could't load shader at path: {} ", shader_path.display()),
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lighting shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_file.into()),
+        });
+
+        let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("light_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lighting Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &light_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::COLOR,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: Default::default(),
+            })
+        };
+        let keep_alpha = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let shade_pipeline = create_pipeline(
+            "Light Shade Pipeline",
+            "fs_shade",
+            wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::Zero,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+        );
+        let glow_pipeline = create_pipeline(
+            "Light Glow Pipeline",
+            "fs_glow",
+            wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+        );
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Uniform Buffer"),
+            contents: bytemuck::bytes_of(&LightUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Light Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let texture = LightRenderer::create_texture(device, UVec2::ONE);
+        let light_bind_group = LightRenderer::create_light_bind_group(
+            device,
+            &light_layout,
+            &uniform_buffer,
+            &texture,
+            &sampler,
+        );
+
+        Self {
+            shade_pipeline,
+            glow_pipeline,
+            light_layout,
+            light_bind_group,
+            uniform_buffer,
+            sampler,
+            texture,
+            materials: LightMaterial::table(assets),
+            key: None,
+            top_chunk: 0,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, size: UVec2) -> Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Light Texture"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    }
+
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        texture: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> BindGroup {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("light_bind_group"),
+        })
+    }
+
+    /// Forgets the light map, so the next `update` computes it again.
+    pub fn invalidate(&mut self) {
+        self.key = None;
+    }
+
+    /// Lights the cells in `view` plus the configured margin, rounded out to
+    /// whole chunks, and sends the light map to the GPU when it's out of
+    /// date. `changed_chunks` are the chunks whose cells changed since the
+    /// last call.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        world: &mut World,
+        view: (Vec2, Vec2),
+        config: &LightingConfig,
+        changed_chunks: &HashSet<IVec2>,
+    ) {
+        let first_chunk =
+            (view.0.floor().as_ivec2() - IVec2::splat(config.margin)).div_euclid(CHUNK_SIZE);
+        let last_chunk =
+            (view.1.ceil().as_ivec2() + IVec2::splat(config.margin)).div_euclid(CHUNK_SIZE);
+        let min = first_chunk * CHUNK_SIZE;
+        let max = ((last_chunk + IVec2::ONE) * CHUNK_SIZE - IVec2::ONE)
+            .min(min + IVec2::splat(MAX_LIGHT_SIZE - 1));
+        let key = LightKey {
+            min,
+            max,
+            falloff: config.falloff,
+        };
+        // Cells between the map and the ground surface can shade it from the sky.
+        let changed = changed_chunks.iter().any(|chunk_pos| {
+            (first_chunk.x..=last_chunk.x).contains(&chunk_pos.x)
+                && (first_chunk.y..=self.top_chunk).contains(&chunk_pos.y)
+        });
+        if changed || self.key.as_ref() != Some(&key) {
+            let sources: Vec<&dyn ChunkSource> = world
+                .storage
+                .iter_mut()
+                .filter_map(|object| object.chunk_source().map(|source| &*source))
+                .collect();
+            let light_map =
+                LightMap::compute_with(&sources, &self.materials, min, max, config.falloff);
+            self.upload(device, queue, &light_map);
+            self.top_chunk = (light_map.sky_top - 1).div_euclid(CHUNK_SIZE.y);
+            self.key = Some(key);
+        }
+
+        let [r, g, b] = config.ambient;
+        let uniform = LightUniform {
+            origin: min.as_vec2().to_array(),
+            size: [self.texture.width() as f32, self.texture.height() as f32],
+            ambient: [r, g, b, config.min_light],
+            glow: config.glow,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, light_map: &LightMap) {
+        if light_map.size.x != self.texture.width() || light_map.size.y != self.texture.height() {
+            self.texture.destroy();
+            self.texture = LightRenderer::create_texture(device, light_map.size);
+            self.light_bind_group = LightRenderer::create_light_bind_group(
+                device,
+                &self.light_layout,
+                &self.uniform_buffer,
+                &self.texture,
+                &self.sampler,
+            );
+        }
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            &light_map.texels(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * light_map.size.x),
+                rows_per_image: None,
+            },
+            self.texture.size(),
+        );
+    }
+
+    /// Composites the last `update` over what's already in `render_pass`.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        for pipeline in [&self.shade_pipeline, &self.glow_pipeline] {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+}
@@ -1,23 +1,46 @@
-pub mod app;
-pub mod app_state;
-pub mod camera;
-pub mod constants;
-pub mod enums;
-pub mod instance_data;
-pub mod objects;
-pub mod utils;
-pub mod world;
+use std::process::ExitCode;
 
-use app::App;
+use clap::Parser;
+use game_engine_wgpu::{
+    app::App,
+    engine_config::{CliArgs, EngineConfig},
+};
 use winit::event_loop::{ControlFlow, EventLoop};
 
-fn main() {
-    env_logger::init();
-    let event_loop = EventLoop::new().unwrap();
+fn main() -> ExitCode {
+    let args = CliArgs::parse();
+    let config = match EngineConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(errors) = config.validate() {
+        for e in errors {
+            eprintln!("error: {e}");
+        }
+        return ExitCode::from(2);
+    }
+    config.init_logger();
+
+    let event_loop = match EventLoop::new() {
+        Ok(event_loop) => event_loop,
+        Err(e) => {
+            eprintln!("error: couldn't start the event loop: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    let mut app = App::new(config);
     if let Err(e) = event_loop.run_app(&mut app) {
         eprintln!("Event loop error: {e}");
+        return ExitCode::FAILURE;
+    }
+    if let Some(e) = app.error {
+        eprintln!("error: {e}");
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
@@ -1,16 +1,47 @@
 pub mod sand;
 
+use std::ops::Range;
+
 use glam::Vec2;
-use winit::{
-    event::WindowEvent,
-    keyboard::{KeyCode, PhysicalKey},
-};
+use winit::event::WindowEvent;
+
+use crate::{input::InputState, instance_data::InstanceData, world::WorldObject};
 
-use crate::{instance_data::InstanceData, world::WorldObject};
+/// Movement speed in cells per second.
+const PLAYER_SPEED: f32 = 30.0;
+
+/// Cells the player is drawn on along each axis, relative to its position.
+const PLAYER_CELLS: Range<i32> = 15..20;
 
 pub struct Player {
     pub(crate) name: String,
     pub(crate) position: Vec2,
+    pub(crate) previous_position: Vec2,
+    pub(crate) direction: Vec2,
+}
+
+impl Player {
+    pub fn new(name: &str, position: Vec2) -> Self {
+        Self {
+            name: name.to_string(),
+            position,
+            previous_position: position,
+            direction: Vec2::ZERO,
+        }
+    }
+
+    fn instances_at(&self, position: Vec2) -> Vec<InstanceData> {
+        let mut instances = vec![];
+        for x in PLAYER_CELLS {
+            for y in PLAYER_CELLS {
+                instances.push(InstanceData {
+                    position: Vec2::new(x as f32, y as f32) + position,
+                    color: 0,
+                })
+            }
+        }
+        instances
+    }
 }
 
 impl WorldObject for Player {
@@ -23,16 +54,20 @@ impl WorldObject for Player {
     }
 
     fn render(&self) -> Vec<crate::instance_data::InstanceData> {
-        let mut instances = vec![];
-        for x in 15..20 {
-            for y in 15..20 {
-                instances.push(InstanceData {
-                    position: Vec2::new(x as f32, y as f32) + self.position,
-                    color: 0,
-                })
-            }
-        }
-        instances
+        self.instances_at(self.position)
+    }
+
+    fn render_interpolated(&self, alpha: f32) -> Vec<InstanceData> {
+        self.instances_at(self.previous_position.lerp(self.position, alpha))
+    }
+
+    fn bounds(&self) -> Option<(Vec2, Vec2)> {
+        let min = self.previous_position.min(self.position);
+        let max = self.previous_position.max(self.position);
+        // Cells are centred on their position, so they reach half a cell out.
+        let first = PLAYER_CELLS.start as f32 - 0.5;
+        let last = PLAYER_CELLS.end as f32 - 0.5;
+        Some((min + Vec2::splat(first), max + Vec2::splat(last)))
     }
 
     fn get_name(&self) -> String {
@@ -40,40 +75,18 @@ impl WorldObject for Player {
     }
 
     fn update(&mut self, delta_t: f32) {
-        // info!("{}", self.get_pos().to_string())
+        self.previous_position = self.position;
+        if self.direction != Vec2::ZERO {
+            self.position += self.direction.normalize() * PLAYER_SPEED * delta_t;
+        }
     }
 
-    fn input(&mut self, delta_t: f32, event: &winit::event::WindowEvent, mouse_position: Vec2) {
-        match event {
-            WindowEvent::CursorMoved { .. } => {
-                // info!(
-                //     "input for object: {}, mouse pos: {:?}",
-                //     self.get_name(),
-                //     position
-                // );
-            }
-            WindowEvent::KeyboardInput { event, .. } => {
-                let mut direction = Vec2::ZERO;
-                match event.physical_key {
-                    PhysicalKey::Code(KeyCode::KeyW) => {
-                        direction.y = 1.0;
-                    }
-                    PhysicalKey::Code(KeyCode::KeyA) => {
-                        direction.x = -1.0;
-                    }
-                    PhysicalKey::Code(KeyCode::KeyS) => {
-                        direction.y = -1.0;
-                    }
-                    PhysicalKey::Code(KeyCode::KeyD) => {
-                        direction.x = 1.0;
-                    }
-                    _ => {}
-                }
-                if direction != Vec2::ZERO {
-                    self.position += direction.normalize() * 0.9;
-                }
-            }
-            _ => {}
-        }
+    fn input(&mut self, _delta_t: f32, _event: &WindowEvent, _mouse_position: Vec2) {}
+
+    fn poll_input(&mut self, input: &InputState) {
+        self.direction = input.action_axis(
+            "player",
+            ["move_left", "move_right", "move_down", "move_up"],
+        );
     }
 }
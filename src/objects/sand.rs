@@ -1,31 +1,57 @@
+pub mod brush;
+pub mod compression;
+pub mod generator;
+pub mod history;
+pub mod image;
+pub mod save;
+pub mod selection;
+pub mod stamp;
+pub mod stats;
+pub mod streaming;
+
 use crate::enums::CellPhysicsType;
 use glam::{IVec2, Vec2};
-use hashbrown::HashMap;
-use log::info;
+use hashbrown::{HashMap, HashSet};
+use log::{debug, info, warn};
 use rayon::prelude::*;
+use std::sync::Arc;
 use turborand::{rng::Rng, *};
-use winit::{
-    event::{ElementState, MouseButton},
-    keyboard::{KeyCode, PhysicalKey},
-};
 
 use crate::{
-    enums::{cell_assets::CellAssets, CHUNK_SIZE, CHUNK_SIZE_LEN},
+    chunk_renderer::{pack_cell, ChunkSource},
+    enums::{cell_assets::CellAssets, CHUNK_SIZE, CHUNK_SIZE_LEN, CHUNK_SLEEP_TICKS},
+    input::{ActionEvent, InputState},
     instance_data::InstanceData,
+    utils::{cell_shade, rects_overlap},
     world::WorldObject,
 };
 
+use brush::{Brush, BrushShape};
+use compression::CompressedChunk;
+use generator::WorldGenerator;
+use history::EditHistory;
+use selection::{Selection, SelectionDrag};
+use stamp::{Stamp, StampLibrary};
+use streaming::{ChunkStore, StreamingConfig};
+
 #[derive(Clone, Copy)]
 pub struct Chunk {
     pub cells: [(usize, Vec2); CHUNK_SIZE_LEN],
+    /// Colour shade of every cell, moved along with it. 0 means the cell
+    /// hasn't moved since it spawned and takes the shade of its position.
+    pub shades: [u8; CHUNK_SIZE_LEN],
     pub cell_count: usize,
+    /// Physics ticks since anything in this chunk last changed.
+    pub idle_ticks: u32,
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Self {
             cells: [(0, Vec2::ZERO); CHUNK_SIZE_LEN],
+            shades: [0; CHUNK_SIZE_LEN],
             cell_count: 0,
+            idle_ticks: 0,
         }
     }
 }
@@ -34,13 +60,19 @@ impl Chunk {
     pub fn new_full(to_full: usize) -> Self {
         let mut cells = [(to_full, Vec2::ZERO); CHUNK_SIZE_LEN];
 
-        for i in CHUNK_SIZE_LEN / 2..CHUNK_SIZE_LEN - 1 {
-            cells[i] = (0, Vec2::ZERO)
+        for cell in cells
+            .iter_mut()
+            .take(CHUNK_SIZE_LEN - 1)
+            .skip(CHUNK_SIZE_LEN / 2)
+        {
+            *cell = (0, Vec2::ZERO)
         }
 
         Self {
             cells,
+            shades: [0; CHUNK_SIZE_LEN],
             cell_count: 0,
+            idle_ticks: 0,
         }
     }
 
@@ -56,12 +88,33 @@ impl Chunk {
     }
 
     pub fn insert(&mut self, pos: IVec2, cell: (usize, Vec2)) {
-        match self.get_mut(pos) {
-            Some(some_cell) => {
-                *some_cell = cell;
+        if let Some(index) = Chunk::ivec_to_vec_index(pos) {
+            self.set_cell(index, cell);
+        }
+    }
+
+    /// Writes a newly spawned cell at `index`.
+    pub fn set_cell(&mut self, index: usize, cell: (usize, Vec2)) {
+        self.cells[index] = cell;
+        self.shades[index] = 0;
+        self.idle_ticks = 0;
+    }
+
+    /// Swaps two cells and their shades. Cells moving for the first time
+    /// keep the shade of where they spawned.
+    pub fn swap_cells(&mut self, chunk_pos: IVec2, i: usize, j: usize) {
+        for index in [i, j] {
+            if self.shades[index] == 0 {
+                self.shades[index] = Chunk::spawn_shade(chunk_pos, index);
             }
-            None => {}
         }
+        self.cells.swap(i, j);
+        self.shades.swap(i, j);
+    }
+
+    fn spawn_shade(chunk_pos: IVec2, index: usize) -> u8 {
+        let local = Chunk::vec_index_to_ivec(index).unwrap_or_default();
+        cell_shade(chunk_pos * CHUNK_SIZE + local)
     }
 
     pub fn ivec_to_vec_index(pos: IVec2) -> Option<usize> {
@@ -107,40 +160,80 @@ impl Chunk {
         Chunk::ivec_to_vec_index(pos).is_some()
     }
 
-    fn render(&self, chunk_pos: IVec2) -> Vec<InstanceData> {
-        let mut material_data = vec![];
-        let chunk_pos_local = (CellWorld::calculate_chunk_pos(chunk_pos) * CHUNK_SIZE).as_vec2();
-
-        for y in 0..CHUNK_SIZE.y {
-            for x in 0..CHUNK_SIZE.x {
-                let cell_pos = IVec2 { x, y };
-                if let Some(cell) = self.get(cell_pos) {
-                    if cell.0 != 0 {
-                        material_data.push(InstanceData {
-                            position: (cell_pos.as_vec2() + cell.1 + chunk_pos_local),
-                            color: (cell.0 - 1) as u32,
-                        })
-                    }
-                }
-            }
-        }
-        material_data
+    /// Material id and shade of every cell in index order, see `pack_cell`.
+    pub fn materials(&self, chunk_pos: IVec2) -> Vec<u32> {
+        self.cells
+            .iter()
+            .zip(self.shades.iter())
+            .enumerate()
+            .map(|(index, (cell, shade))| {
+                let shade = match *shade {
+                    0 => Chunk::spawn_shade(chunk_pos, index),
+                    shade => shade,
+                };
+                pack_cell(cell.0 as u32, shade)
+            })
+            .collect()
     }
 }
 
+/// Physics seed of worlds without a generator, so headless runs repeat.
+pub const DEFAULT_SEED: u64 = 0;
+
 #[derive(Default)]
 pub struct CellWorld {
     pub position: Vec2,
     pub chunks: HashMap<IVec2, Chunk>,
+    /// Sleeping chunks, decompressed back into `chunks` when accessed mutably.
+    pub compressed_chunks: HashMap<IVec2, CompressedChunk>,
+    /// Chunks outside of the streaming radius, see `stream_chunks`.
+    pub unloaded_chunks: ChunkStore,
+    pub streaming: StreamingConfig,
+    /// World position chunks are streamed and simulated around.
+    pub focus: Vec2,
+    pub last_focus_chunk: Option<IVec2>,
+    /// Fills chunks that are touched for the first time, they stay empty without one.
+    pub generator: Option<Arc<dyn WorldGenerator>>,
     pub chunk_count: i32,
     pub assets: CellAssets,
     pub rand: Rng,
-    pub is_move: bool,
     pub selected: u32,
+    /// Cell painted last frame while a paint button is held, strokes are
+    /// joined from here so fast drags don't leave gaps.
+    pub last_paint: Option<IVec2>,
+    pub brush: Brush,
+    pub selection: Option<Selection>,
+    /// World area on screen, chunks outside of it aren't drawn.
+    pub view: Option<(Vec2, Vec2)>,
+    /// Chunks in render range but off screen at the last `visible_chunks`.
+    pub culled_chunks: usize,
+    /// Bumped whenever `selection` changes, see `render_revision`.
+    pub selection_revision: u64,
+    pub selection_drag: Option<SelectionDrag>,
+    pub history: EditHistory,
+    pub clipboard: Option<Stamp>,
+    pub stamps: StampLibrary,
+    /// Chunks whose cells changed since the renderer last uploaded them.
+    pub dirty_chunks: HashSet<IVec2>,
+    /// Set by the first `take_dirty_chunks`, worlds nothing draws don't
+    /// collect dirty chunks.
+    pub tracks_dirty_chunks: bool,
 }
 
 impl CellWorld {
+    /// Queues the chunk for upload if a renderer is reading them.
+    pub fn mark_dirty(&mut self, chunk_pos: IVec2) {
+        if self.tracks_dirty_chunks {
+            self.dirty_chunks.insert(chunk_pos);
+        }
+    }
+
     pub fn insert(&mut self, pos: IVec2, cell: (usize, Vec2)) {
+        self.record_change(pos, cell.0);
+        self.mark_dirty(CellWorld::calculate_chunk_pos(pos));
+        if self.generate_chunk(CellWorld::calculate_chunk_pos(pos)) {
+            self.wake_chunk(CellWorld::calculate_chunk_pos(pos));
+        }
         match self.get_mut_chunk(pos) {
             Some(chunk) => chunk.insert(Chunk::global_pos_to_chunk_pos(pos), cell),
             None => {
@@ -154,31 +247,148 @@ impl CellWorld {
     }
 
     pub fn get_mut_chunk(&mut self, pos: IVec2) -> Option<&mut Chunk> {
-        self.chunks.get_mut(&CellWorld::calculate_chunk_pos(pos))
+        let chunk_pos = CellWorld::calculate_chunk_pos(pos);
+        self.wake_chunk(chunk_pos);
+        self.chunks.get_mut(&chunk_pos)
     }
 
     // pub fn get_mut_or_create_chunk(&mut self, pos: IVec2) -> &mut Chunk {
 
     // }
 
+    /// Only returns awake chunks, sleeping ones are read through `get`.
     pub fn get_chunk(&self, pos: IVec2) -> Option<&Chunk> {
         self.chunks.get(&CellWorld::calculate_chunk_pos(pos))
     }
 
+    /// Decompresses a sleeping or unloaded chunk back into `chunks`.
+    pub fn wake_chunk(&mut self, chunk_pos: IVec2) {
+        if let Some(compressed) = self.compressed_chunks.remove(&chunk_pos) {
+            self.chunks.insert(chunk_pos, compressed.decompress());
+        } else if self.unloaded_chunks.contains(chunk_pos) {
+            match self.unloaded_chunks.take(chunk_pos) {
+                Ok(Some(compressed)) => {
+                    self.chunks.insert(chunk_pos, compressed.decompress());
+                }
+                Ok(None) => {}
+                Err(e) => warn!("couldn't load chunk {}: {}", chunk_pos, e),
+            }
+        }
+    }
+
+    /// Whether the chunk exists in any state, awake, sleeping or unloaded.
+    pub fn has_chunk(&self, chunk_pos: IVec2) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+            || self.compressed_chunks.contains_key(&chunk_pos)
+            || self.unloaded_chunks.contains(chunk_pos)
+    }
+
+    /// Runs the generator for a chunk that has never been touched. Returns
+    /// whether a chunk was generated.
+    pub fn generate_chunk(&mut self, chunk_pos: IVec2) -> bool {
+        if self.has_chunk(chunk_pos) {
+            return false;
+        }
+        let Some(generator) = self.generator.clone() else {
+            return false;
+        };
+        self.add_generated_chunk(chunk_pos, generator.generate(chunk_pos));
+        true
+    }
+
+    /// Uniform chunks, like open air or solid rock, start out asleep.
+    fn add_generated_chunk(&mut self, chunk_pos: IVec2, chunk: Chunk) {
+        self.mark_dirty(chunk_pos);
+        let compressed = CompressedChunk::compress(&chunk);
+        if let CompressedChunk::Uniform(_) = compressed {
+            self.compressed_chunks.insert(chunk_pos, compressed);
+        } else {
+            self.chunks.insert(chunk_pos, chunk);
+        }
+        self.chunk_count += 1;
+    }
+
+    /// Generates every missing chunk within `radius` of `center` in parallel.
+    pub fn generate_around(&mut self, center: IVec2, radius: i32) -> usize {
+        let Some(generator) = self.generator.clone() else {
+            return 0;
+        };
+        let missing: Vec<IVec2> = (-radius..=radius)
+            .flat_map(|x| (-radius..=radius).map(move |y| center + IVec2::new(x, y)))
+            .filter(|chunk_pos| !self.has_chunk(*chunk_pos))
+            .collect();
+
+        let generated: Vec<(IVec2, Chunk)> = missing
+            .par_iter()
+            .map(|chunk_pos| (*chunk_pos, generator.generate(*chunk_pos)))
+            .collect();
+
+        let count = generated.len();
+        for (chunk_pos, chunk) in generated {
+            self.add_generated_chunk(chunk_pos, chunk);
+        }
+        count
+    }
+
+    /// Compresses every chunk that has been idle for `CHUNK_SLEEP_TICKS`.
+    pub fn sleep_idle_chunks(&mut self) {
+        let idle: Vec<IVec2> = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| chunk.idle_ticks >= CHUNK_SLEEP_TICKS)
+            .map(|(pos, _)| *pos)
+            .collect();
+
+        for chunk_pos in idle {
+            if let Some(chunk) = self.chunks.remove(&chunk_pos) {
+                let compressed = CompressedChunk::compress(&chunk);
+                debug!(
+                    "chunk {} went to sleep, {} runs",
+                    chunk_pos,
+                    compressed.run_count()
+                );
+                self.compressed_chunks.insert(chunk_pos, compressed);
+            }
+        }
+    }
+
     pub fn is_cell_empty(&self, pos: IVec2) -> bool {
         self.get(pos).is_none()
     }
 
     pub fn get(&self, pos: IVec2) -> Option<(usize, Vec2)> {
         let chunk_pos = CellWorld::calculate_chunk_pos(pos);
-        self.chunks
-            .get(&chunk_pos)
-            .and_then(|chunk| chunk.get(Chunk::global_pos_to_chunk_pos(pos)))
+        let local_pos = Chunk::global_pos_to_chunk_pos(pos);
+        match self.chunks.get(&chunk_pos) {
+            Some(chunk) => chunk.get(local_pos),
+            None => self
+                .compressed_chunks
+                .get(&chunk_pos)
+                .and_then(|chunk| chunk.get(local_pos)),
+        }
     }
 
-    pub fn get_mut(&mut self, pos: IVec2) -> Option<&mut (usize, Vec2)> {
+    /// Like `get`, but generates or reloads the chunk first if needed.
+    pub fn load_cell(&mut self, pos: IVec2) -> Option<(usize, Vec2)> {
         let chunk_pos = CellWorld::calculate_chunk_pos(pos);
-        self.chunks.get_mut(&chunk_pos)?.get_mut(pos % CHUNK_SIZE)
+        self.generate_chunk(chunk_pos);
+        if self.unloaded_chunks.contains(chunk_pos) {
+            self.wake_chunk(chunk_pos);
+        }
+        self.get(pos)
+    }
+
+    pub fn get_mut(&mut self, pos: IVec2) -> Option<&mut (usize, Vec2)> {
+        self.mark_dirty(CellWorld::calculate_chunk_pos(pos));
+        let chunk = self.get_mut_chunk(pos)?;
+        chunk.idle_ticks = 0;
+        chunk.get_mut(Chunk::global_pos_to_chunk_pos(pos))
+    }
+
+    /// World area covered by a chunk, cells are centred on their position.
+    pub fn chunk_rect(chunk_pos: IVec2) -> (Vec2, Vec2) {
+        let min = (chunk_pos * CHUNK_SIZE).as_vec2() - Vec2::splat(0.5);
+        (min, min + CHUNK_SIZE.as_vec2())
     }
 
     pub fn calculate_chunk_pos(pos: IVec2) -> IVec2 {
@@ -219,47 +429,75 @@ impl CellWorld {
         Self {
             position: Vec2::ZERO,
             chunks,
+            compressed_chunks: HashMap::new(),
             chunk_count: 1,
             assets,
-            rand: Rng::new(),
-            is_move: false,
+            rand: Rng::with_seed(DEFAULT_SEED),
             selected: 1,
+            ..Default::default()
         }
     }
 
-    pub fn select_cell_type(&mut self, keycode: PhysicalKey, state: ElementState) {
-        match (keycode, state) {
-            (PhysicalKey::Code(KeyCode::KeyR), ElementState::Released) => {
-                self.selected = if self.assets.get((self.selected - 1) as usize).is_some() {
-                    info!(
-                        "selected {:?}",
-                        self.assets.get((self.selected - 1) as usize)
-                    );
-                    self.selected - 1
-                } else {
-                    self.selected
-                }
-            }
-            (PhysicalKey::Code(KeyCode::KeyT), ElementState::Released) => {
-                self.selected = if self.assets.get((self.selected + 1) as usize).is_some() {
-                    info!(
-                        "selected {:?}",
-                        self.assets.get((self.selected + 1) as usize)
-                    );
-                    self.selected + 1
-                } else {
-                    self.selected
-                }
-            }
-            _ => {}
+    /// Reseeds the physics randomness, which is otherwise seeded with
+    /// `DEFAULT_SEED` or the generator seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rand = Rng::with_seed(seed);
+        self
+    }
+
+    /// Starts without any chunks, they are filled in by `generator` as the
+    /// focus moves around.
+    pub fn generated(assets: CellAssets, generator: Arc<dyn WorldGenerator>) -> Self {
+        Self {
+            assets,
+            rand: Rng::with_seed(generator.seed()),
+            selected: 1,
+            generator: Some(generator),
+            ..Default::default()
         }
     }
 
-    pub fn physics(&mut self) {
-        if !self.is_move {
-            return;
+    pub fn select_previous(&mut self) {
+        self.selected = if self.assets.get((self.selected - 1) as usize).is_some() {
+            info!(
+                "selected {:?}",
+                self.assets.get((self.selected - 1) as usize)
+            );
+            self.selected - 1
+        } else {
+            self.selected
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = if self.assets.get((self.selected + 1) as usize).is_some() {
+            info!(
+                "selected {:?}",
+                self.assets.get((self.selected + 1) as usize)
+            );
+            self.selected + 1
+        } else {
+            self.selected
         }
-        for (chunk_pos, chunk) in self.chunks.iter_mut() {
+    }
+
+    pub fn physics(&mut self) {
+        let focus_chunk = self.focus_chunk();
+        let simulation_radius = self.streaming.simulation_radius;
+
+        // Sorted so the shared rng is consumed in the same order every run.
+        let mut simulated: Vec<IVec2> = self
+            .chunks
+            .keys()
+            .filter(|pos| (**pos - focus_chunk).abs().max_element() <= simulation_radius)
+            .copied()
+            .collect();
+        simulated.sort_by_key(|pos| (pos.x, pos.y));
+
+        for chunk_pos in simulated {
+            let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+                continue;
+            };
             let mut to_swap_list: Vec<_> = vec![];
             let mut to_move_list = vec![];
             let mut to_insert_list = vec![];
@@ -276,8 +514,11 @@ impl CellWorld {
                 )
             }
 
+            let changed =
+                !to_swap_list.is_empty() || !to_move_list.is_empty() || !to_insert_list.is_empty();
+
             for (i, j) in to_swap_list {
-                chunk.cells.swap(i, j);
+                chunk.swap_cells(chunk_pos, i, j);
             }
             for to_move in to_move_list {
                 if let Some(cell) = chunk.cells.get_mut(to_move.1) {
@@ -285,18 +526,27 @@ impl CellWorld {
                 }
             }
             for to_insert in to_insert_list {
-                if let Some(cell) = chunk.cells.get_mut(to_insert.0) {
-                    *cell = to_insert.1;
+                if to_insert.0 < CHUNK_SIZE_LEN {
+                    chunk.set_cell(to_insert.0, to_insert.1);
                 }
             }
+
+            if changed {
+                chunk.idle_ticks = 0;
+                self.mark_dirty(chunk_pos);
+            } else {
+                chunk.idle_ticks = chunk.idle_ticks.saturating_add(1);
+            }
         }
+
+        self.sleep_idle_chunks();
     }
 }
 
 fn cell_physics(
     to_swap_list: &mut Vec<(usize, usize)>,
     to_insert_list: &mut Vec<(usize, (usize, Vec2))>,
-    to_move_list: &mut Vec<(Vec2, usize)>,
+    to_move_list: &mut [(Vec2, usize)],
     i: usize,
     chunk: &Chunk,
     assets: &CellAssets,
@@ -306,8 +556,8 @@ fn cell_physics(
     if cell.0 == 0 {
         return;
     };
-    match assets.get(chunk.cells[i].0 - 1) {
-        Some(behavior) => match behavior.physics_behavior {
+    if let Some(behavior) = assets.get(chunk.cells[i].0 - 1) {
+        match behavior.physics_behavior {
             CellPhysicsType::Sand => {
                 sand_physics(i, chunk, to_swap_list, to_move_list, rand);
             }
@@ -315,11 +565,10 @@ fn cell_physics(
                 fluid_physics(i, chunk, to_swap_list, to_move_list, rand);
             }
             CellPhysicsType::Tap(to_spawn) => {
-                tap_physics(to_insert_list, i, chunk, &to_spawn, assets, rand);
+                tap_physics(to_insert_list, i, chunk, &to_spawn, assets);
             }
             CellPhysicsType::Solid => {}
-        },
-        None => {}
+        }
     }
 }
 
@@ -332,64 +581,182 @@ impl WorldObject for CellWorld {
         self.position = pos;
     }
 
+    /// Cells are drawn by the chunk renderer, see `chunk_source`. Only the
+    /// selection outline is drawn as instances.
     fn render(&self) -> Vec<InstanceData> {
-        let pos_and_chunks = self.chunks.iter().enumerate();
-
-        let instance_data_vec = pos_and_chunks
-            .into_iter() // Clone to avoid borrowing issues
-            .par_bridge() // Use par_bridge to enable parallel processing
-            .map(|(index, chunk)| chunk.1.render(*chunk.0 * CHUNK_SIZE))
-            .collect::<Vec<_>>();
-        let mut data = vec![];
-        for mut arr in instance_data_vec {
-            data.append(&mut arr)
-        }
-        data
-    }
-
-    fn input(&mut self, delta_t: f32, event: &winit::event::WindowEvent, mouse_position: Vec2) {
-        match event {
-            winit::event::WindowEvent::KeyboardInput { event, .. } => {
-                match (event.physical_key, event.state) {
-                    (PhysicalKey::Code(KeyCode::KeyR), ElementState::Released) => {
-                        self.is_move = !self.is_move;
-                    }
-                    (code, state) => self.select_cell_type(code, state),
-                    _ => {}
+        self.render_selection()
+    }
+
+    fn render_revision(&self) -> Option<u64> {
+        Some(self.selection_revision)
+    }
+
+    fn chunk_source(&mut self) -> Option<&mut dyn ChunkSource> {
+        Some(self)
+    }
+
+    fn input(&mut self, _delta_t: f32, _event: &winit::event::WindowEvent, _mouse_position: Vec2) {}
+
+    fn input_context(&self) -> Option<&str> {
+        Some("editor")
+    }
+
+    fn on_action(&mut self, action: &ActionEvent, mouse_position: Vec2) {
+        if !action.just_pressed() {
+            return;
+        }
+        match action.action.as_str() {
+            "select_previous" => self.select_previous(),
+            "select_next" => self.select_next(),
+            "brush_bigger" => self.brush.resize(1),
+            "brush_smaller" => self.brush.resize(-1),
+            "brush_shape" => self.brush.next_shape(),
+            "spray_denser" => self.brush.adjust_density(brush::SPRAY_DENSITY_STEP),
+            "spray_sparser" => self.brush.adjust_density(-brush::SPRAY_DENSITY_STEP),
+            "brush_mode" => self.brush.toggle_mode(),
+            "flood_fill" | "selection_delete" | "selection_convert" | "selection_clear"
+            | "selection_up" | "selection_down" | "selection_left" | "selection_right" => {
+                self.end_stroke();
+                self.begin_operation(&action.action);
+                self.selection_action(&action.action, mouse_position.as_ivec2());
+                self.end_operation();
+            }
+            "copy" | "cut" | "paste" | "stamp_rotate" | "stamp_mirror" | "stamp_flip"
+            | "stamp_save" | "stamp_next" => {
+                self.end_stroke();
+                self.begin_operation(&action.action);
+                self.stamp_action(&action.action, mouse_position.as_ivec2());
+                self.end_operation();
+            }
+            "undo" => {
+                self.end_stroke();
+                self.undo();
+            }
+            "redo" => {
+                self.end_stroke();
+                self.redo();
+            }
+            "quicksave" => {
+                if let Err(e) = self.save(save::QUICKSAVE_PATH) {
+                    warn!("couldn't save world: {}", e);
                 }
             }
-            winit::event::WindowEvent::MouseInput { state, button, .. } => match (state, button) {
-                (ElementState::Pressed, MouseButton::Left) => self.insert(
-                    mouse_position.as_ivec2(),
-                    (self.selected as usize, Vec2::ZERO),
-                ),
-                (ElementState::Pressed, MouseButton::Right) => {
-                    self.insert(mouse_position.as_ivec2(), (0, Vec2::ZERO))
+            "quickload" => {
+                self.end_stroke();
+                if let Err(e) = self.load(save::QUICKSAVE_PATH) {
+                    warn!("couldn't load world: {}", e);
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     }
 
-    fn update(&mut self, delta_t: f32) {
+    fn poll_input(&mut self, input: &InputState) {
+        self.poll_selection_input(input);
+
+        let (cell, brush, name) = if input.action_active("editor", "paint") {
+            (self.selected as usize, self.brush, "paint")
+        } else if input.action_active("editor", "erase") {
+            (0, self.brush.eraser(), "erase")
+        } else {
+            self.end_stroke();
+            return;
+        };
+        if self.last_paint.is_none() {
+            self.begin_operation(name);
+        }
+        let to = input.cursor_world.as_ivec2();
+        let from = self.last_paint.unwrap_or(to);
+        match brush.shape {
+            BrushShape::Spray => self.spray_line(from, to, &brush, cell, input.frame_time),
+            _ => self.paint_line(from, to, &brush, cell),
+        };
+        self.last_paint = Some(to);
+    }
+
+    fn update(&mut self, _delta_t: f32) {
+        self.stream_chunks();
         self.physics()
     }
 
+    fn set_focus(&mut self, focus: Vec2) {
+        self.focus = focus;
+    }
+
+    fn set_view(&mut self, view: (Vec2, Vec2)) {
+        self.view = Some(view);
+    }
+
     fn get_name(&self) -> String {
         "cell world".to_string()
     }
 }
 
+impl ChunkSource for CellWorld {
+    fn visible_chunks(&mut self) -> Vec<IVec2> {
+        let in_range: Vec<IVec2> = self
+            .chunks
+            .keys()
+            .chain(self.compressed_chunks.keys())
+            .filter(|pos| self.is_chunk_rendered(**pos))
+            .copied()
+            .collect();
+        let mut visible: Vec<IVec2> = match self.view {
+            Some(view) => in_range
+                .iter()
+                .filter(|pos| rects_overlap(CellWorld::chunk_rect(**pos), view))
+                .copied()
+                .collect(),
+            None => in_range.clone(),
+        };
+        self.culled_chunks = in_range.len() - visible.len();
+        visible.sort_by_key(|pos| (pos.x, pos.y));
+        visible
+    }
+
+    fn culled_chunks(&self) -> usize {
+        self.culled_chunks
+    }
+
+    fn take_dirty_chunks(&mut self) -> Vec<IVec2> {
+        self.tracks_dirty_chunks = true;
+        self.dirty_chunks.drain().collect()
+    }
+
+    fn chunk_materials(&self, chunk_pos: IVec2) -> Option<Vec<u32>> {
+        match self.chunks.get(&chunk_pos) {
+            Some(chunk) => Some(chunk.materials(chunk_pos)),
+            None => self
+                .compressed_chunks
+                .get(&chunk_pos)
+                .map(|chunk| chunk.materials(chunk_pos)),
+        }
+    }
+
+    fn chunk_ids(&self, chunk_pos: IVec2) -> Option<Vec<u32>> {
+        match self.chunks.get(&chunk_pos) {
+            Some(chunk) => Some(chunk.cells.iter().map(|cell| cell.0 as u32).collect()),
+            None => self.compressed_chunks.get(&chunk_pos).map(|chunk| {
+                chunk
+                    .runs()
+                    .flat_map(|run| std::iter::repeat_n(run.cell.0 as u32, run.len as usize))
+                    .collect()
+            }),
+        }
+    }
+
+    fn sky_height(&self, x: i32) -> Option<i32> {
+        self.generator.as_ref()?.surface_height(x)
+    }
+}
+
 fn sand_physics(
     i: usize,
     chunk: &Chunk,
     to_swap_list: &mut Vec<(usize, usize)>,
-    to_move_list: &mut Vec<(Vec2, usize)>,
+    _to_move_list: &mut [(Vec2, usize)],
     rand: &mut Rng,
 ) {
-    let pos = Chunk::vec_index_to_ivec(i).unwrap();
-
     if let Some(pos_below) = Chunk::get_index_below(i) {
         if chunk.cells[pos_below].0 == 0 {
             to_swap_list.push((i, pos_below));
@@ -418,7 +785,6 @@ fn tap_physics(
     chunk: &Chunk,
     to_spawn: &String,
     assets: &CellAssets,
-    rand: &mut Rng,
 ) {
     if let Some(i_below) = get_is_none_below(chunk, i) {
         if let Some(asset_id) = assets.get_index_by_name(to_spawn.to_string()) {
@@ -431,11 +797,9 @@ fn fluid_physics(
     i: usize,
     chunk: &Chunk,
     to_swap_list: &mut Vec<(usize, usize)>,
-    to_move_list: &mut Vec<(Vec2, usize)>,
+    _to_move_list: &mut [(Vec2, usize)],
     rand: &mut Rng,
 ) {
-    let pos = Chunk::vec_index_to_ivec(i).unwrap();
-
     if let Some(pos_below) = Chunk::get_index_below(i) {
         if chunk.cells[pos_below].0 == 0 {
             to_swap_list.push((i, pos_below));
@@ -464,6 +828,7 @@ fn fluid_physics(
     move_if_none(to_swap_list, is_none_left, is_none_right, i, rand)
 }
 
+#[allow(dead_code)]
 fn get_is_none_by_offset_vec2(chunk: &Chunk, pos: IVec2, offset: IVec2) -> Option<usize> {
     let mut pos_offset = pos;
     pos_offset += offset;
@@ -472,7 +837,7 @@ fn get_is_none_by_offset_vec2(chunk: &Chunk, pos: IVec2, offset: IVec2) -> Optio
 }
 
 fn get_is_none_below(chunk: &Chunk, i: usize) -> Option<usize> {
-    let i_below = i - CHUNK_SIZE.y as usize;
+    let i_below = Chunk::get_index_below(i)?;
     if let Some(cell) = chunk.cells.get(i_below) {
         if cell.0 == 0 {
             return Some(i_below);
@@ -482,7 +847,7 @@ fn get_is_none_below(chunk: &Chunk, i: usize) -> Option<usize> {
 }
 
 fn get_is_none_left(chunk: &Chunk, i: usize) -> Option<usize> {
-    let i_below = i - 1;
+    let i_below = i.checked_sub(1)?;
     if let Some(cell) = chunk.cells.get(i_below) {
         if cell.0 == 0 {
             return Some(i_below);
@@ -501,6 +866,7 @@ fn get_is_none_right(chunk: &Chunk, i: usize) -> Option<usize> {
     None
 }
 
+#[allow(dead_code)]
 fn get_is_none_up(chunk: &Chunk, i: usize) -> Option<usize> {
     let i_below = i + CHUNK_SIZE.y as usize;
     if let Some(cell) = chunk.cells.get(i_below) {
@@ -512,7 +878,7 @@ fn get_is_none_up(chunk: &Chunk, i: usize) -> Option<usize> {
 }
 
 fn get_is_none_below_right(chunk: &Chunk, i: usize) -> Option<usize> {
-    let i_below = i - CHUNK_SIZE.y as usize - 1;
+    let i_below = i.checked_sub(CHUNK_SIZE.y as usize + 1)?;
     if let Some(cell) = chunk.cells.get(i_below) {
         if cell.0 == 0 {
             return Some(i_below);
@@ -531,6 +897,7 @@ fn get_is_none_below_left(chunk: &Chunk, i: usize) -> Option<usize> {
     None
 }
 
+#[allow(dead_code)]
 fn get_is_none_up_right(chunk: &Chunk, i: usize) -> Option<usize> {
     let i_below = i + CHUNK_SIZE.y as usize - 1;
     if let Some(cell) = chunk.cells.get(i_below) {
@@ -541,6 +908,7 @@ fn get_is_none_up_right(chunk: &Chunk, i: usize) -> Option<usize> {
     None
 }
 
+#[allow(dead_code)]
 fn get_is_none_up_left(chunk: &Chunk, i: usize) -> Option<usize> {
     let i_below = i + CHUNK_SIZE.y as usize + 1;
     if let Some(cell) = chunk.cells.get(i_below) {
@@ -0,0 +1,323 @@
+use glam::{IVec2, UVec2, Vec2};
+use log::info;
+use serde::{Deserialize, Serialize};
+use turborand::prelude::*;
+
+use crate::utils::line_cells;
+
+use super::CellWorld;
+
+/// Largest brush radius the editor keys can reach.
+pub const MAX_BRUSH_SIZE: u32 = 32;
+
+/// Step of the spray density keys.
+pub const SPRAY_DENSITY_STEP: f32 = 0.05;
+
+/// Seconds the spray takes to paint `Brush::density` of its area.
+pub const SPRAY_INTERVAL: f32 = 1.0 / 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BrushShape {
+    Circle,
+    Square,
+    /// Circle painted a few random cells at a time, see `CellWorld::spray_line`.
+    Spray,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushMode {
+    #[default]
+    Overwrite,
+    /// Only fills empty cells, existing material is left alone.
+    ReplaceEmpty,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Brush {
+    pub shape: BrushShape,
+    /// Radius in cells, 0 paints a single cell.
+    pub size: u32,
+    pub mode: BrushMode,
+    /// Share of the brush area the spray paints every `SPRAY_INTERVAL`,
+    /// from 0 to 1.
+    pub density: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            shape: BrushShape::Circle,
+            size: 2,
+            mode: BrushMode::Overwrite,
+            density: 0.1,
+        }
+    }
+}
+
+impl Brush {
+    /// Brush used for erasing, same shape and size but always overwrites.
+    pub fn eraser(&self) -> Self {
+        Self {
+            mode: BrushMode::Overwrite,
+            ..*self
+        }
+    }
+
+    pub fn next_shape(&mut self) {
+        self.shape = match self.shape {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::Spray,
+            BrushShape::Spray => BrushShape::Circle,
+        };
+        info!("brush shape {:?}", self.shape);
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            BrushMode::Overwrite => BrushMode::ReplaceEmpty,
+            BrushMode::ReplaceEmpty => BrushMode::Overwrite,
+        };
+        info!("brush mode {:?}", self.mode);
+    }
+
+    pub fn resize(&mut self, delta: i32) {
+        self.size = self.size.saturating_add_signed(delta).min(MAX_BRUSH_SIZE);
+        info!("brush size {}", self.size);
+    }
+
+    pub fn adjust_density(&mut self, delta: f32) {
+        self.density = (self.density + delta).clamp(SPRAY_DENSITY_STEP, 1.0);
+        info!("spray density {}", self.density);
+    }
+}
+
+impl CellWorld {
+    /// Writes one cell, honouring `mode`. Returns whether the cell changed.
+    pub fn paint_cell(&mut self, pos: IVec2, cell: usize, mode: BrushMode) -> bool {
+        match self.load_cell(pos) {
+            Some((current, _)) if current == cell => return false,
+            Some((current, _)) if current != 0 && mode == BrushMode::ReplaceEmpty => return false,
+            _ => {}
+        }
+        self.insert(pos, (cell, Vec2::ZERO));
+        true
+    }
+
+    /// Returns the number of cells changed.
+    pub fn paint_circle(
+        &mut self,
+        center: IVec2,
+        radius: u32,
+        cell: usize,
+        mode: BrushMode,
+    ) -> usize {
+        self.paint_circle_with_density(center, radius, cell, mode, 1.0)
+    }
+
+    fn paint_circle_with_density(
+        &mut self,
+        center: IVec2,
+        radius: u32,
+        cell: usize,
+        mode: BrushMode,
+        density: f32,
+    ) -> usize {
+        let radius = radius as i32;
+        let mut changed = 0;
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if x * x + y * y > radius * radius {
+                    continue;
+                }
+                if density < 1.0 && self.rand.f32() >= density {
+                    continue;
+                }
+                changed += self.paint_cell(center + IVec2::new(x, y), cell, mode) as usize;
+            }
+        }
+        changed
+    }
+
+    /// Fills `min..min + size`. Returns the number of cells changed.
+    pub fn fill_rect(&mut self, min: IVec2, size: UVec2, cell: usize, mode: BrushMode) -> usize {
+        let mut changed = 0;
+        for y in min.y..min.y + size.y as i32 {
+            for x in min.x..min.x + size.x as i32 {
+                changed += self.paint_cell(IVec2::new(x, y), cell, mode) as usize;
+            }
+        }
+        changed
+    }
+
+    /// Paints one dab of `brush` centred on `center`.
+    pub fn paint(&mut self, center: IVec2, brush: &Brush, cell: usize) -> usize {
+        match brush.shape {
+            BrushShape::Circle => self.paint_circle(center, brush.size, cell, brush.mode),
+            BrushShape::Square => self.fill_rect(
+                center - IVec2::splat(brush.size as i32),
+                UVec2::splat(brush.size * 2 + 1),
+                cell,
+                brush.mode,
+            ),
+            BrushShape::Spray => {
+                self.paint_circle_with_density(center, brush.size, cell, brush.mode, brush.density)
+            }
+        }
+    }
+
+    /// Drags `brush` from `from` to `to`. Dabs are spaced half the brush
+    /// radius apart so wide strokes don't repaint every cell many times.
+    pub fn paint_line(&mut self, from: IVec2, to: IVec2, brush: &Brush, cell: usize) -> usize {
+        let step = (brush.size as usize / 2).max(1);
+        let cells = line_cells(from, to);
+        let last = cells.len() - 1;
+        cells
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| i % step == 0 || *i == last)
+            .map(|(_, pos)| self.paint(pos, brush, cell))
+            .sum()
+    }
+
+    /// Sprays for `elapsed` seconds along the stroke from `from` to `to`.
+    /// Drops land at random along the stroke, so the amount painted depends
+    /// on the time held rather than on how many frames that took.
+    pub fn spray_line(
+        &mut self,
+        from: IVec2,
+        to: IVec2,
+        brush: &Brush,
+        cell: usize,
+        elapsed: f32,
+    ) -> usize {
+        let radius = brush.size as i32;
+        let area = std::f32::consts::PI * (brush.size as f32 + 0.5).powi(2);
+        let drops = brush.density * area * elapsed / SPRAY_INTERVAL;
+        let count = drops as usize + (self.rand.f32() < drops.fract()) as usize;
+
+        let mut changed = 0;
+        for _ in 0..count {
+            let center = from.as_vec2().lerp(to.as_vec2(), self.rand.f32());
+            let offset = loop {
+                let offset = IVec2::new(
+                    self.rand.i32(-radius..=radius),
+                    self.rand.i32(-radius..=radius),
+                );
+                if offset.length_squared() <= radius * radius {
+                    break offset;
+                }
+            };
+            changed +=
+                self.paint_cell(center.round().as_ivec2() + offset, cell, brush.mode) as usize;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAND: usize = 7;
+
+    fn brush(shape: BrushShape, size: u32) -> Brush {
+        Brush {
+            shape,
+            size,
+            ..Default::default()
+        }
+    }
+
+    fn painted(world: &CellWorld) -> usize {
+        world
+            .chunks
+            .values()
+            .flat_map(|chunk| chunk.cells.iter())
+            .filter(|cell| cell.0 != 0)
+            .count()
+    }
+
+    #[test]
+    fn circle_and_square_cover_their_area() {
+        let mut world = CellWorld::default();
+        assert_eq!(
+            world.paint(IVec2::ZERO, &brush(BrushShape::Circle, 0), SAND),
+            1
+        );
+        assert_eq!(
+            world.paint(IVec2::new(50, 50), &brush(BrushShape::Circle, 2), SAND),
+            13
+        );
+        assert_eq!(
+            world.paint(IVec2::new(-50, 50), &brush(BrushShape::Square, 2), SAND),
+            25
+        );
+        assert_eq!(painted(&world), 39);
+        assert_eq!(world.get(IVec2::new(-48, 52)), Some((SAND, Vec2::ZERO)));
+        assert_eq!(world.get(IVec2::new(52, 52)), Some((0, Vec2::ZERO)));
+    }
+
+    #[test]
+    fn replace_empty_keeps_material() {
+        let mut world = CellWorld::default();
+        world.paint(IVec2::ZERO, &brush(BrushShape::Square, 1), SAND);
+        let brush = Brush {
+            mode: BrushMode::ReplaceEmpty,
+            ..brush(BrushShape::Square, 2)
+        };
+        assert_eq!(world.paint(IVec2::ZERO, &brush, 1), 16);
+        assert_eq!(world.get(IVec2::ZERO), Some((SAND, Vec2::ZERO)));
+        assert_eq!(world.paint(IVec2::ZERO, &brush.eraser(), 0), 25);
+        assert_eq!(painted(&world), 0);
+    }
+
+    #[test]
+    fn line_has_no_gaps() {
+        let mut world = CellWorld::default();
+        world.paint_line(
+            IVec2::ZERO,
+            IVec2::new(30, 10),
+            &brush(BrushShape::Circle, 0),
+            SAND,
+        );
+        assert_eq!(
+            painted(&world),
+            line_cells(IVec2::ZERO, IVec2::new(30, 10)).len()
+        );
+    }
+
+    /// Holds the spray still for a second at `fps`.
+    fn spray_for_a_second(fps: u32) -> usize {
+        let mut world = CellWorld::default();
+        let brush = Brush {
+            density: SPRAY_DENSITY_STEP,
+            ..brush(BrushShape::Spray, 20)
+        };
+        for _ in 0..fps {
+            world.spray_line(IVec2::ZERO, IVec2::ZERO, &brush, SAND, 1.0 / fps as f32);
+        }
+        painted(&world)
+    }
+
+    #[test]
+    fn spray_does_not_depend_on_frame_rate() {
+        let (slow, fast) = (spray_for_a_second(15), spray_for_a_second(240));
+        let difference = slow.abs_diff(fast) as f32 / slow.max(fast) as f32;
+        assert!(
+            difference < 0.1,
+            "{} cells at 15 fps, {} at 240",
+            slow,
+            fast
+        );
+        assert_eq!(
+            CellWorld::default().spray_line(
+                IVec2::ZERO,
+                IVec2::ZERO,
+                &brush(BrushShape::Spray, 20),
+                SAND,
+                0.0
+            ),
+            0
+        );
+    }
+}
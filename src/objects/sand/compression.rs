@@ -0,0 +1,278 @@
+use glam::{IVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk_renderer::pack_cell,
+    enums::{CHUNK_SIZE, CHUNK_SIZE_LEN},
+    utils::cell_shade,
+};
+
+use super::Chunk;
+
+/// A run of identical cells, stored in row-major chunk order.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CellRun {
+    pub len: u32,
+    pub cell: (usize, Vec2),
+}
+
+/// A run of cells with the same shade as `(shade, len)`, see `Chunk::shades`.
+pub type ShadeRun = (u8, u32);
+
+/// Run-length encoded copy of a `Chunk`.
+///
+/// Used by the save format and for chunks that have been sleeping long enough
+/// to be evicted from `CellWorld::chunks`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CompressedChunk {
+    /// Every cell in the chunk is the same and none of them has moved.
+    Uniform((usize, Vec2)),
+    Runs(Vec<CellRun>),
+    /// Runs of cells some of which have moved, with their shades encoded
+    /// in runs of their own.
+    Shaded {
+        runs: Vec<CellRun>,
+        shades: Vec<ShadeRun>,
+    },
+}
+
+impl CompressedChunk {
+    pub fn compress(chunk: &Chunk) -> Self {
+        let mut runs: Vec<CellRun> = vec![];
+        let mut shades: Vec<ShadeRun> = vec![];
+
+        for (cell, shade) in chunk.cells.iter().zip(chunk.shades.iter()) {
+            match runs.last_mut() {
+                Some(run) if run.cell == *cell => run.len += 1,
+                _ => runs.push(CellRun {
+                    len: 1,
+                    cell: *cell,
+                }),
+            }
+            // Empty cells have no colour, their shade doesn't need keeping.
+            let shade = if cell.0 == 0 { 0 } else { *shade };
+            match shades.last_mut() {
+                Some((last, len)) if *last == shade => *len += 1,
+                _ => shades.push((shade, 1)),
+            }
+        }
+
+        match (runs.as_slice(), shades.as_slice()) {
+            ([run], [(0, _)]) => CompressedChunk::Uniform(run.cell),
+            (_, [(0, _)]) => CompressedChunk::Runs(runs),
+            _ => CompressedChunk::Shaded { runs, shades },
+        }
+    }
+
+    pub fn decompress(&self) -> Chunk {
+        let mut chunk = Chunk::default();
+        let mut index = 0;
+        for run in self.runs() {
+            let end = (index + run.len as usize).min(CHUNK_SIZE_LEN);
+            chunk.cells[index..end].fill(run.cell);
+            index = end;
+        }
+        if let CompressedChunk::Shaded { shades, .. } = self {
+            let mut index = 0;
+            for (shade, len) in shades {
+                let end = (index + *len as usize).min(CHUNK_SIZE_LEN);
+                chunk.shades[index..end].fill(*shade);
+                index = end;
+            }
+        }
+        chunk
+    }
+
+    /// Every run of cells in index order, a uniform chunk is one run over
+    /// the whole chunk.
+    pub fn runs(&self) -> impl Iterator<Item = CellRun> + '_ {
+        let (uniform, runs) = match self {
+            CompressedChunk::Uniform(cell) => (
+                Some(CellRun {
+                    len: CHUNK_SIZE_LEN as u32,
+                    cell: *cell,
+                }),
+                &[][..],
+            ),
+            CompressedChunk::Runs(runs) | CompressedChunk::Shaded { runs, .. } => {
+                (None, runs.as_slice())
+            }
+        };
+        uniform.into_iter().chain(runs.iter().copied())
+    }
+
+    /// Every run of shades in index order, chunks where nothing has moved
+    /// are one run of 0 over the whole chunk.
+    pub fn shade_runs(&self) -> impl Iterator<Item = ShadeRun> + '_ {
+        let (unshaded, shades) = match self {
+            CompressedChunk::Shaded { shades, .. } => (None, shades.as_slice()),
+            _ => (Some((0, CHUNK_SIZE_LEN as u32)), &[][..]),
+        };
+        unshaded.into_iter().chain(shades.iter().copied())
+    }
+
+    /// Reads a single cell without decompressing the whole chunk.
+    pub fn get(&self, pos: IVec2) -> Option<(usize, Vec2)> {
+        let index = Chunk::ivec_to_vec_index(pos)?;
+        let mut start = 0;
+        for run in self.runs() {
+            start += run.len as usize;
+            if index < start {
+                return Some(run.cell);
+            }
+        }
+        None
+    }
+
+    pub fn run_count(&self) -> usize {
+        match self {
+            CompressedChunk::Uniform(_) => 1,
+            CompressedChunk::Runs(runs) => runs.len(),
+            CompressedChunk::Shaded { runs, shades } => runs.len() + shades.len(),
+        }
+    }
+
+    /// Material id and shade of every cell in index order, without building
+    /// the whole `Chunk`, see `Chunk::materials`.
+    pub fn materials(&self, chunk_pos: IVec2) -> Vec<u32> {
+        let origin = chunk_pos * CHUNK_SIZE;
+        let ids = self
+            .runs()
+            .flat_map(|run| std::iter::repeat_n(run.cell.0 as u32, run.len as usize));
+        let shades = self
+            .shade_runs()
+            .flat_map(|(shade, len)| std::iter::repeat_n(shade, len as usize));
+        let mut cells: Vec<u32> = ids
+            .zip(shades)
+            .take(CHUNK_SIZE_LEN)
+            .enumerate()
+            .map(|(index, (id, shade))| {
+                let shade = match shade {
+                    0 => {
+                        let local = Chunk::vec_index_to_ivec(index).unwrap_or_default();
+                        cell_shade(origin + local)
+                    }
+                    shade => shade,
+                };
+                pack_cell(id, shade)
+            })
+            .collect();
+        cells.resize(CHUNK_SIZE_LEN, 0);
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        chunk_renderer::MATERIAL_BITS, enums::cell_assets::import_assets, objects::sand::CellWorld,
+    };
+
+    use super::*;
+
+    /// A block of sand dropped to the bottom of chunk 0, with nothing below,
+    /// and left to settle.
+    fn simulated_chunk() -> Chunk {
+        let assets = import_assets(Path::new("assets")).expect("assets load");
+        let sand = assets
+            .get_index_by_name("Sand".to_string())
+            .expect("sand asset")
+            + 1;
+        let mut world = CellWorld {
+            assets,
+            ..Default::default()
+        };
+        for x in 40..60 {
+            for y in 60..80 {
+                world.insert(IVec2::new(x, y), (sand, Vec2::ZERO));
+            }
+        }
+        for _ in 0..100 {
+            world.physics();
+        }
+        world.chunks[&IVec2::ZERO]
+    }
+
+    /// Packed cells with empty ones cleared, their shade isn't drawn.
+    fn drawn(materials: Vec<u32>) -> Vec<u32> {
+        materials
+            .into_iter()
+            .map(|cell| match cell & ((1 << MATERIAL_BITS) - 1) {
+                0 => 0,
+                _ => cell,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_chunk_is_uniform() {
+        let compressed = CompressedChunk::compress(&Chunk::default());
+        assert_eq!(compressed, CompressedChunk::Uniform((0, Vec2::ZERO)));
+        assert_eq!(compressed.decompress().cells, Chunk::default().cells);
+        assert_eq!(compressed.get(IVec2::new(99, 99)), Some((0, Vec2::ZERO)));
+    }
+
+    #[test]
+    fn uniform_round_trip() {
+        let mut chunk = Chunk::default();
+        chunk.cells.fill((3, Vec2::ZERO));
+        let compressed = CompressedChunk::compress(&chunk);
+        assert_eq!(compressed, CompressedChunk::Uniform((3, Vec2::ZERO)));
+        assert_eq!(compressed.run_count(), 1);
+        assert_eq!(compressed.decompress().cells, chunk.cells);
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let mut chunk = Chunk::default();
+        chunk.cells[..CHUNK_SIZE_LEN / 2].fill((2, Vec2::ZERO));
+        chunk.cells[10] = (5, Vec2::new(0.0, 1.5));
+        let compressed = CompressedChunk::compress(&chunk);
+        assert!(matches!(compressed, CompressedChunk::Runs(_)));
+        assert_eq!(compressed.run_count(), 4);
+        assert_eq!(compressed.decompress().cells, chunk.cells);
+        assert_eq!(
+            compressed.get(IVec2::new(10, 0)),
+            Some((5, Vec2::new(0.0, 1.5)))
+        );
+        assert_eq!(compressed.get(IVec2::new(99, 99)), Some((0, Vec2::ZERO)));
+        assert_eq!(
+            compressed.materials(IVec2::ZERO),
+            chunk.materials(IVec2::ZERO)
+        );
+    }
+
+    #[test]
+    fn shades_survive_compression() {
+        let chunk = simulated_chunk();
+        assert!(chunk.shades.iter().any(|shade| *shade != 0));
+        let compressed = CompressedChunk::compress(&chunk);
+        let restored = compressed.decompress();
+        assert_eq!(restored.cells, chunk.cells);
+        let expected = drawn(chunk.materials(IVec2::ZERO));
+        assert_eq!(drawn(restored.materials(IVec2::ZERO)), expected);
+        assert_eq!(drawn(compressed.materials(IVec2::ZERO)), expected);
+    }
+
+    #[test]
+    fn simulated_chunk_still_compresses() {
+        let chunk = simulated_chunk();
+        let compressed = CompressedChunk::compress(&chunk);
+        // One run per moved sand cell, plus the air around them.
+        assert!(
+            compressed.run_count() < 500,
+            "{} runs",
+            compressed.run_count()
+        );
+        let saved = ron::to_string(&compressed).expect("chunk serializes");
+        let raw_shades = ron::to_string(&chunk.shades.to_vec()).expect("shades serialize");
+        assert!(
+            saved.len() < raw_shades.len() / 2,
+            "{} bytes, raw shades alone take {}",
+            saved.len(),
+            raw_shades.len()
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::{fs, path::Path};
+
+use glam::{IVec2, Vec2};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::enums::{cell_assets::CellAssets, CHUNK_SIZE};
+
+use super::Chunk;
+
+/// Fills chunks the first time they are touched. Implementations must be
+/// deterministic for a given chunk position, so a chunk generated again
+/// matches the first one. Evicted chunks are still kept in
+/// `unloaded_chunks`, as they may have been edited since.
+pub trait WorldGenerator: Send + Sync {
+    fn generate(&self, chunk_pos: IVec2) -> Chunk;
+
+    fn seed(&self) -> u64;
+
+    /// Lowest height from which column `x` is generated empty, if the
+    /// generator has a single ground surface.
+    fn surface_height(&self, _x: i32) -> Option<i32> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LayerConfig {
+    pub material: String,
+    /// Thickness in cells, the last layer extends downwards forever.
+    pub depth: i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CaveConfig {
+    pub frequency: f32,
+    /// Noise values above this are carved out.
+    pub threshold: f32,
+    /// Caves don't break through the surface above this depth.
+    pub min_depth: i32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 0.03,
+            threshold: 0.62,
+            min_depth: 12,
+        }
+    }
+}
+
+/// Ore veins and water pockets, placed where their own noise field passes
+/// `threshold`. Pockets with `in_caves` only fill carved out space.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DepositConfig {
+    pub material: String,
+    pub frequency: f32,
+    pub threshold: f32,
+    #[serde(default)]
+    pub min_depth: i32,
+    #[serde(default)]
+    pub in_caves: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NoiseGeneratorConfig {
+    pub seed: u64,
+    pub surface_height: f32,
+    pub surface_amplitude: f32,
+    pub surface_frequency: f32,
+    pub octaves: u32,
+    pub layers: Vec<LayerConfig>,
+    pub caves: Option<CaveConfig>,
+    pub deposits: Vec<DepositConfig>,
+}
+
+impl Default for NoiseGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            surface_height: 0.0,
+            surface_amplitude: 40.0,
+            surface_frequency: 0.01,
+            octaves: 4,
+            layers: vec![],
+            caves: None,
+            deposits: vec![],
+        }
+    }
+}
+
+impl NoiseGeneratorConfig {
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(contents) => match toml::from_str(&contents) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    warn!("couldn't parse generator config: {}", e);
+                    None
+                }
+            },
+            Err(e) => {
+                warn!(
+                    "couldn't read generator config at {}: {}",
+                    path.as_ref().display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+struct Deposit {
+    cell: usize,
+    frequency: f32,
+    threshold: f32,
+    min_depth: i32,
+    in_caves: bool,
+}
+
+/// Heightmap terrain with layered materials, caves and deposits.
+pub struct NoiseGenerator {
+    config: NoiseGeneratorConfig,
+    layers: Vec<(usize, i32)>,
+    deposits: Vec<Deposit>,
+}
+
+impl NoiseGenerator {
+    /// Resolves material names against `assets`, failing on unknown names.
+    pub fn new(config: NoiseGeneratorConfig, assets: &CellAssets) -> Result<Self, String> {
+        let cell_id = |name: &String| {
+            assets
+                .get_index_by_name(name.clone())
+                .map(|index| index + 1)
+                .ok_or_else(|| format!("generator references unknown material: {}", name))
+        };
+
+        let layers = config
+            .layers
+            .iter()
+            .map(|layer| Ok((cell_id(&layer.material)?, layer.depth)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let deposits = config
+            .deposits
+            .iter()
+            .map(|deposit| {
+                Ok(Deposit {
+                    cell: cell_id(&deposit.material)?,
+                    frequency: deposit.frequency,
+                    threshold: deposit.threshold,
+                    min_depth: deposit.min_depth,
+                    in_caves: deposit.in_caves,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        info!(
+            "world generator ready, seed {}, {} layers, {} deposits",
+            config.seed,
+            layers.len(),
+            deposits.len()
+        );
+
+        Ok(Self {
+            config,
+            layers,
+            deposits,
+        })
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    fn surface_at(&self, x: i32) -> f32 {
+        let noise = fbm(
+            Vec2::new(x as f32 * self.config.surface_frequency, 0.0),
+            self.config.seed,
+            self.config.octaves,
+        );
+        self.config.surface_height + (noise * 2.0 - 1.0) * self.config.surface_amplitude
+    }
+
+    fn layer_at(&self, depth: i32) -> usize {
+        let mut bottom = 0;
+        for (cell, layer_depth) in self.layers.iter() {
+            bottom += layer_depth;
+            if depth < bottom {
+                return *cell;
+            }
+        }
+        self.layers.last().map(|(cell, _)| *cell).unwrap_or(0)
+    }
+
+    fn cell_at(&self, pos: IVec2, surface: f32) -> usize {
+        let depth = (surface - pos.y as f32).floor() as i32;
+        if depth < 0 {
+            return 0;
+        }
+
+        let is_cave = self.config.caves.as_ref().is_some_and(|caves| {
+            depth >= caves.min_depth
+                && fbm(
+                    pos.as_vec2() * caves.frequency,
+                    self.config.seed.wrapping_add(1),
+                    self.config.octaves,
+                ) > caves.threshold
+        });
+
+        for (i, deposit) in self.deposits.iter().enumerate() {
+            if depth < deposit.min_depth || deposit.in_caves != is_cave {
+                continue;
+            }
+            let noise = fbm(
+                pos.as_vec2() * deposit.frequency,
+                self.config.seed.wrapping_add(2 + i as u64),
+                self.config.octaves,
+            );
+            if noise > deposit.threshold {
+                return deposit.cell;
+            }
+        }
+
+        if is_cave {
+            0
+        } else {
+            self.layer_at(depth)
+        }
+    }
+}
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, chunk_pos: IVec2) -> Chunk {
+        let mut chunk = Chunk::default();
+        let origin = chunk_pos * CHUNK_SIZE;
+
+        for x in 0..CHUNK_SIZE.x {
+            let surface = self.surface_at(origin.x + x);
+            for y in 0..CHUNK_SIZE.y {
+                let local_pos = IVec2::new(x, y);
+                let cell = self.cell_at(origin + local_pos, surface);
+                if cell != 0 {
+                    chunk.insert(local_pos, (cell, Vec2::ZERO));
+                }
+            }
+        }
+        chunk
+    }
+
+    fn seed(&self) -> u64 {
+        self.config.seed
+    }
+
+    fn surface_height(&self, x: i32) -> Option<i32> {
+        // Cells at or below the surface are ground, see `cell_at`.
+        Some(self.surface_at(x).floor() as i32 + 1)
+    }
+}
+
+fn hash(x: i32, y: i32, seed: u64) -> f32 {
+    let mut h = seed
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .wrapping_add((x as u32 as u64) << 32 | y as u32 as u64);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    h ^= h >> 33;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smoothly interpolated value noise in `[0, 1)`.
+fn value_noise(pos: Vec2, seed: u64) -> f32 {
+    let cell = pos.floor();
+    let t = pos - cell;
+    let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+    let (x, y) = (cell.x as i32, cell.y as i32);
+
+    let bottom = lerp(hash(x, y, seed), hash(x + 1, y, seed), t.x);
+    let top = lerp(hash(x, y + 1, seed), hash(x + 1, y + 1, seed), t.x);
+    lerp(bottom, top, t.y)
+}
+
+fn fbm(pos: Vec2, seed: u64, octaves: u32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+    let mut total = 0.0;
+    let mut pos = pos;
+    for octave in 0..octaves.max(1) {
+        value += value_noise(pos, seed.wrapping_add(octave as u64)) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        pos *= 2.0;
+    }
+    value / total
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enums::cell_assets::import_assets;
+
+    use super::*;
+
+    const CHUNKS: [IVec2; 4] = [
+        IVec2::new(0, -1),
+        IVec2::new(0, 0),
+        IVec2::new(-3, 1),
+        IVec2::new(5, 2),
+    ];
+
+    fn generator(seed: u64) -> NoiseGenerator {
+        let assets = import_assets(Path::new("assets")).expect("assets load");
+        let config = NoiseGeneratorConfig::load("assets/generator.toml").expect("config loads");
+        NoiseGenerator::new(config, &assets)
+            .expect("generator builds")
+            .with_seed(seed)
+    }
+
+    #[test]
+    fn same_seed_generates_same_chunks() {
+        let (first, second) = (generator(7), generator(7));
+        for chunk_pos in CHUNKS {
+            assert_eq!(
+                first.generate(chunk_pos).cells,
+                second.generate(chunk_pos).cells,
+                "chunk {}",
+                chunk_pos
+            );
+            assert_eq!(
+                first.surface_height(chunk_pos.x * CHUNK_SIZE.x),
+                second.surface_height(chunk_pos.x * CHUNK_SIZE.x)
+            );
+        }
+    }
+
+    #[test]
+    fn regenerating_a_chunk_repeats_it() {
+        let generator = generator(7);
+        let chunk = generator.generate(IVec2::new(1, 1));
+        generator.generate(IVec2::new(2, 1));
+        assert_eq!(generator.generate(IVec2::new(1, 1)).cells, chunk.cells);
+    }
+
+    #[test]
+    fn seed_changes_terrain() {
+        let (first, second) = (generator(7), generator(8));
+        assert!(
+            CHUNKS
+                .iter()
+                .any(|chunk_pos| first.generate(*chunk_pos).cells
+                    != second.generate(*chunk_pos).cells)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+
+use glam::{IVec2, Vec2};
+use hashbrown::HashMap;
+use log::{info, warn};
+
+use super::CellWorld;
+
+/// Memory the undo history may hold before the oldest operations are dropped.
+pub const DEFAULT_HISTORY_BUDGET: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellChange {
+    pub pos: IVec2,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// One undoable step, like a brush stroke or a flood fill. Only cells that
+/// actually changed are stored.
+#[derive(Clone, Debug)]
+pub struct EditOperation {
+    pub name: String,
+    pub changes: Vec<CellChange>,
+}
+
+impl EditOperation {
+    fn size_bytes(&self) -> usize {
+        self.changes.len() * std::mem::size_of::<CellChange>() + self.name.len()
+    }
+}
+
+/// Operation being recorded, first `before` and last `after` per cell.
+#[derive(Debug)]
+struct OpenOperation {
+    name: String,
+    changes: HashMap<IVec2, (usize, usize)>,
+}
+
+/// Edit journal for `CellWorld`. Writes through `CellWorld::insert` are
+/// recorded while an operation is open, see `begin_operation`.
+///
+/// Undo restores the cells as they were before the edit, whatever the
+/// simulation has done to them since.
+#[derive(Debug)]
+pub struct EditHistory {
+    undo: VecDeque<EditOperation>,
+    redo: Vec<EditOperation>,
+    current: Option<OpenOperation>,
+    used_bytes: usize,
+    pub budget_bytes: usize,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            current: None,
+            used_bytes: 0,
+            budget_bytes: DEFAULT_HISTORY_BUDGET,
+        }
+    }
+}
+
+impl EditHistory {
+    pub fn is_recording(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Bytes currently held by the undo and redo stacks.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current = None;
+        self.used_bytes = 0;
+    }
+
+    fn record(&mut self, pos: IVec2, before: usize, after: usize) {
+        if let Some(current) = self.current.as_mut() {
+            current
+                .changes
+                .entry(pos)
+                .and_modify(|(_, last)| *last = after)
+                .or_insert((before, after));
+        }
+    }
+
+    fn push(&mut self, operation: EditOperation) {
+        // Whatever was undone can't be redone on top of the new edit.
+        for dropped in self.redo.drain(..) {
+            self.used_bytes -= dropped.size_bytes();
+        }
+        let size = operation.size_bytes();
+        if size > self.budget_bytes {
+            warn!(
+                "{} is too large to undo ({} bytes, budget {})",
+                operation.name, size, self.budget_bytes
+            );
+            return;
+        }
+        self.used_bytes += size;
+        self.undo.push_back(operation);
+        while self.used_bytes > self.budget_bytes {
+            match self.undo.pop_front() {
+                Some(dropped) => self.used_bytes -= dropped.size_bytes(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl CellWorld {
+    /// Starts grouping edits into one undoable operation. Any operation
+    /// still open is finished first.
+    pub fn begin_operation(&mut self, name: &str) {
+        self.end_operation();
+        self.history.current = Some(OpenOperation {
+            name: name.to_string(),
+            changes: HashMap::new(),
+        });
+    }
+
+    /// Closes the open operation and pushes it onto the undo stack if
+    /// anything changed.
+    pub fn end_operation(&mut self) {
+        let Some(OpenOperation { name, changes }) = self.history.current.take() else {
+            return;
+        };
+        let mut changes: Vec<_> = changes
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(pos, (before, after))| CellChange { pos, before, after })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        changes.sort_by_key(|change| (change.pos.y, change.pos.x));
+        self.history.push(EditOperation { name, changes });
+    }
+
+    /// Called by `insert` before a cell is overwritten.
+    pub(crate) fn record_change(&mut self, pos: IVec2, after: usize) {
+        if !self.history.is_recording() {
+            return;
+        }
+        let before = self.load_cell(pos).map_or(0, |(id, _)| id);
+        self.history.record(pos, before, after);
+    }
+
+    /// Finishes the brush stroke in progress, so the next operation doesn't
+    /// run inside it.
+    pub fn end_stroke(&mut self) {
+        if self.last_paint.take().is_some() {
+            self.end_operation();
+        }
+    }
+
+    /// Reverts the last operation. Returns whether there was one.
+    pub fn undo(&mut self) -> bool {
+        self.end_operation();
+        let Some(operation) = self.history.undo.pop_back() else {
+            return false;
+        };
+        for change in operation.changes.iter() {
+            self.insert(change.pos, (change.before, Vec2::ZERO));
+        }
+        info!("undid {}", operation.name);
+        self.history.redo.push(operation);
+        true
+    }
+
+    /// Reapplies the last undone operation. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        self.end_operation();
+        let Some(operation) = self.history.redo.pop() else {
+            return false;
+        };
+        for change in operation.changes.iter() {
+            self.insert(change.pos, (change.after, Vec2::ZERO));
+        }
+        info!("redid {}", operation.name);
+        self.history.undo.push_back(operation);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: usize = 2;
+    const SAND: usize = 7;
+
+    fn id(world: &CellWorld, pos: IVec2) -> usize {
+        world.get(pos).map_or(0, |(id, _)| id)
+    }
+
+    /// Writes `cell` to `count` cells along x as one operation.
+    fn edit(world: &mut CellWorld, name: &str, cell: usize, count: i32) {
+        world.begin_operation(name);
+        for x in 0..count {
+            world.insert(IVec2::new(x, 0), (cell, Vec2::ZERO));
+        }
+        world.end_operation();
+    }
+
+    #[test]
+    fn undo_and_redo_restore_cells() {
+        let mut world = CellWorld::default();
+        edit(&mut world, "sand", SAND, 3);
+        edit(&mut world, "stone", STONE, 2);
+
+        assert!(world.undo());
+        assert_eq!(id(&world, IVec2::ZERO), SAND);
+        assert!(world.undo());
+        assert_eq!(id(&world, IVec2::ZERO), 0);
+        assert!(!world.undo());
+
+        assert!(world.redo());
+        assert_eq!(id(&world, IVec2::new(2, 0)), SAND);
+        assert!(world.redo());
+        assert_eq!(id(&world, IVec2::ZERO), STONE);
+        assert_eq!(id(&world, IVec2::new(2, 0)), SAND);
+        assert!(!world.redo());
+    }
+
+    #[test]
+    fn new_edit_drops_redo() {
+        let mut world = CellWorld::default();
+        edit(&mut world, "sand", SAND, 3);
+        world.undo();
+        assert!(world.history.can_redo());
+        edit(&mut world, "stone", STONE, 1);
+        assert!(!world.history.can_redo());
+        assert_eq!(
+            world.history.used_bytes(),
+            std::mem::size_of::<CellChange>() + "stone".len()
+        );
+    }
+
+    #[test]
+    fn unchanged_cells_are_not_recorded() {
+        let mut world = CellWorld::default();
+        world.begin_operation("noop");
+        world.insert(IVec2::ZERO, (SAND, Vec2::ZERO));
+        world.insert(IVec2::ZERO, (0, Vec2::ZERO));
+        world.end_operation();
+        assert!(!world.history.can_undo());
+    }
+
+    #[test]
+    fn budget_drops_oldest_operations() {
+        let mut world = CellWorld::default();
+        let change = std::mem::size_of::<CellChange>();
+        world.history.budget_bytes = 25 * change;
+        edit(&mut world, "a", SAND, 10);
+        edit(&mut world, "b", STONE, 10);
+        edit(&mut world, "c", SAND, 10);
+        assert!(world.history.used_bytes() <= world.history.budget_bytes);
+
+        assert!(world.undo());
+        assert!(world.undo());
+        assert!(!world.undo());
+        assert_eq!(id(&world, IVec2::ZERO), SAND);
+
+        // Too large to ever fit, the edit stays but can't be undone.
+        edit(&mut world, "huge", STONE, 30);
+        assert!(!world.history.can_undo());
+        assert_eq!(id(&world, IVec2::new(29, 0)), STONE);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{fs, io, path::Path};
+
+use ecolor::Rgba;
+use glam::{IVec2, UVec2, Vec2};
+use hashbrown::HashMap;
+use log::info;
+use serde::Deserialize;
+
+use crate::image::RgbaImage;
+
+use super::{CellWorld, Chunk};
+
+/// Material name that clears cells instead of painting them.
+pub const EMPTY_MATERIAL: &str = "Empty";
+
+/// Explicit pixel colour to material assignments, loaded from TOML:
+///
+/// ```toml
+/// [colors]
+/// "#f6d7b0" = "Sand"
+/// "#ffffff" = "Empty"
+/// ```
+#[derive(Default, Debug, Deserialize)]
+pub struct ColorMapping {
+    #[serde(default)]
+    pub colors: HashMap<String, String>,
+}
+
+impl ColorMapping {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Resolves colours and material names into cell ids.
+    fn resolve(&self, world: &CellWorld) -> io::Result<HashMap<[u8; 3], usize>> {
+        let mut resolved = HashMap::new();
+        for (hex, name) in self.colors.iter() {
+            let color = parse_hex(hex).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid colour in mapping: {}", hex),
+                )
+            })?;
+            let cell = if name == EMPTY_MATERIAL {
+                0
+            } else {
+                world
+                    .assets
+                    .get_index_by_name(name.clone())
+                    .map(|index| index + 1)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("mapping references unknown material: {}", name),
+                        )
+                    })?
+            };
+            resolved.insert(color, cell);
+        }
+        Ok(resolved)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+impl CellWorld {
+    /// Stamps a PNG into the world with its bottom left pixel at `offset`.
+    ///
+    /// Fully transparent pixels are left untouched. Colours missing from
+    /// `mapping` use the closest asset colour. Returns the number of cells written.
+    pub fn import_png(
+        &mut self,
+        path: impl AsRef<Path>,
+        mapping: Option<&ColorMapping>,
+        offset: IVec2,
+    ) -> io::Result<usize> {
+        let image = RgbaImage::load_png(path.as_ref())?;
+        let explicit = match mapping {
+            Some(mapping) => mapping.resolve(self)?,
+            None => HashMap::new(),
+        };
+
+        let mut closest_cache: HashMap<[u8; 3], usize> = HashMap::new();
+        let mut written = 0;
+
+        for (i, pixel) in image.pixels.iter().enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            let cell = match explicit.get(&rgb) {
+                Some(cell) => *cell,
+                None => *closest_cache.entry(rgb).or_insert_with(|| {
+                    let color = Rgba::from_rgb(
+                        rgb[0] as f32 / 255.0,
+                        rgb[1] as f32 / 255.0,
+                        rgb[2] as f32 / 255.0,
+                    );
+                    self.assets
+                        .closest_by_color(color)
+                        .map(|index| index + 1)
+                        .unwrap_or(0)
+                }),
+            };
+
+            let x = (i as u32 % image.width) as i32;
+            let y = (image.height - 1 - i as u32 / image.width) as i32;
+            self.insert(offset + IVec2::new(x, y), (cell, Vec2::ZERO));
+            written += 1;
+        }
+
+        info!(
+            "imported {}x{} image from {} at {}, {} cells",
+            image.width,
+            image.height,
+            path.as_ref().display(),
+            offset,
+            written
+        );
+        Ok(written)
+    }
+
+    /// Draws the cells in `min..min + size` with one pixel per cell using the
+    /// asset colours. Empty cells are transparent, so the result can be fed
+    /// back into `import_png`. Unloaded chunks are read from the chunk store.
+    pub fn rasterize(&self, min: IVec2, size: UVec2) -> io::Result<RgbaImage> {
+        let first_chunk = CellWorld::calculate_chunk_pos(min);
+        let last_chunk = CellWorld::calculate_chunk_pos(min + size.as_ivec2() - IVec2::ONE);
+        let mut unloaded = HashMap::new();
+        for chunk_y in first_chunk.y..=last_chunk.y {
+            for chunk_x in first_chunk.x..=last_chunk.x {
+                let chunk_pos = IVec2::new(chunk_x, chunk_y);
+                if let Some(chunk) = self.unloaded_chunks.read(chunk_pos)? {
+                    unloaded.insert(chunk_pos, chunk);
+                }
+            }
+        }
+        let get = |pos: IVec2| {
+            self.get(pos).or_else(|| {
+                unloaded
+                    .get(&CellWorld::calculate_chunk_pos(pos))
+                    .and_then(|chunk| chunk.get(Chunk::global_pos_to_chunk_pos(pos)))
+            })
+        };
+
+        let mut pixels = Vec::with_capacity((size.x * size.y) as usize);
+        for row in 0..size.y {
+            let y = min.y + (size.y - 1 - row) as i32;
+            for x in min.x..min.x + size.x as i32 {
+                let pixel = match get(IVec2::new(x, y)) {
+                    Some((cell, _)) if cell != 0 => {
+                        match self.assets.assets_color_vec.get(cell - 1) {
+                            Some(color) => {
+                                let [r, g, b, _] = color.to_array();
+                                [to_u8(r), to_u8(g), to_u8(b), 255]
+                            }
+                            None => [255, 0, 0, 255],
+                        }
+                    }
+                    _ => [0, 0, 0, 0],
+                };
+                pixels.push(pixel);
+            }
+        }
+
+        Ok(RgbaImage {
+            width: size.x,
+            height: size.y,
+            pixels,
+        })
+    }
+
+    /// Writes the cells in `min..min + size` to a PNG, see `rasterize`.
+    pub fn export_png(&self, path: impl AsRef<Path>, min: IVec2, size: UVec2) -> io::Result<()> {
+        self.rasterize(min, size)?.save_png(path.as_ref())?;
+        info!(
+            "exported {}x{} cells at {} to {}",
+            size.x,
+            size.y,
+            min,
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+fn to_u8(channel: f32) -> u8 {
+    (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+}
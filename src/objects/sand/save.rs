@@ -0,0 +1,215 @@
+use std::{fs, io, path::Path};
+
+use glam::{IVec2, Vec2};
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::enums::cell_assets::CellAssets;
+
+use super::{compression::CompressedChunk, CellWorld};
+
+/// Version 2 added the shades of moved cells, version 1 saves still load.
+pub const SAVE_VERSION: u32 = 2;
+
+pub const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+/// On-disk representation of a `CellWorld`.
+///
+/// Cells store asset ids, so the material names in use when the world was
+/// saved are kept alongside the chunks and remapped on load.
+#[derive(Serialize, Deserialize)]
+pub struct WorldSave {
+    pub version: u32,
+    pub materials: Vec<String>,
+    pub chunks: Vec<(IVec2, CompressedChunk)>,
+}
+
+impl WorldSave {
+    pub fn from_world(world: &CellWorld) -> io::Result<Self> {
+        let chunks = world.snapshot_chunks()?;
+
+        Ok(Self {
+            version: SAVE_VERSION,
+            materials: world
+                .assets
+                .assets
+                .iter()
+                .map(|asset| asset.name.clone())
+                .collect(),
+            chunks,
+        })
+    }
+
+    /// Maps the saved asset ids onto the ids of `assets`. Materials that no
+    /// longer exist are loaded as empty cells.
+    fn remap_materials(&mut self, assets: &CellAssets) {
+        let remap: Vec<usize> = self
+            .materials
+            .iter()
+            .map(|name| match assets.get_index_by_name(name.clone()) {
+                Some(index) => index + 1,
+                None => {
+                    warn!("save references unknown material: {}", name);
+                    0
+                }
+            })
+            .collect();
+
+        let remap_cell = |cell: &mut (usize, Vec2)| {
+            if cell.0 != 0 {
+                cell.0 = remap.get(cell.0 - 1).copied().unwrap_or(0);
+            }
+        };
+
+        for (_, chunk) in self.chunks.iter_mut() {
+            match chunk {
+                CompressedChunk::Uniform(cell) => remap_cell(cell),
+                CompressedChunk::Runs(runs) | CompressedChunk::Shaded { runs, .. } => {
+                    for run in runs.iter_mut() {
+                        remap_cell(&mut run.cell)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CellWorld {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let save = WorldSave::from_world(self)?;
+        let contents = ron::to_string(&save).map_err(io::Error::other)?;
+        fs::write(path.as_ref(), contents)?;
+        info!(
+            "saved {} chunks to {}",
+            save.chunks.len(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    /// Replaces the chunks of this world with the ones stored at `path`.
+    /// Loaded chunks start out compressed and are woken up on first access,
+    /// the next `stream_chunks` evicts the ones out of range.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        let mut save: WorldSave =
+            ron::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if !(1..=SAVE_VERSION).contains(&save.version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported save version {}, expected {}",
+                    save.version, SAVE_VERSION
+                ),
+            ));
+        }
+
+        save.remap_materials(&self.assets);
+
+        self.chunks.clear();
+        self.unloaded_chunks.clear();
+        self.history.clear();
+        self.last_focus_chunk = None;
+        self.compressed_chunks = save.chunks.into_iter().collect::<HashMap<_, _>>();
+        if self.tracks_dirty_chunks {
+            self.dirty_chunks = self.compressed_chunks.keys().copied().collect();
+        }
+        self.chunk_count = self.compressed_chunks.len() as i32;
+        info!(
+            "loaded {} chunks from {}",
+            self.chunk_count,
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enums::cell_assets::import_assets;
+
+    use super::{super::compression::CellRun, *};
+
+    fn assets() -> CellAssets {
+        import_assets(Path::new("assets")).expect("assets load")
+    }
+
+    fn id(assets: &CellAssets, name: &str) -> usize {
+        assets.get_index_by_name(name.to_string()).expect("asset") + 1
+    }
+
+    #[test]
+    fn remaps_saved_ids_by_name() {
+        let assets = assets();
+        let mut save = WorldSave {
+            version: SAVE_VERSION,
+            materials: vec![
+                "Sand".to_string(),
+                "Removed".to_string(),
+                "Water".to_string(),
+            ],
+            chunks: vec![
+                (IVec2::ZERO, CompressedChunk::Uniform((1, Vec2::ZERO))),
+                (
+                    IVec2::X,
+                    CompressedChunk::Runs(vec![
+                        CellRun {
+                            len: 10,
+                            cell: (2, Vec2::ZERO),
+                        },
+                        CellRun {
+                            len: 10,
+                            cell: (3, Vec2::ZERO),
+                        },
+                        CellRun {
+                            len: 10,
+                            cell: (0, Vec2::ZERO),
+                        },
+                    ]),
+                ),
+            ],
+        };
+        save.remap_materials(&assets);
+
+        assert_eq!(
+            save.chunks[0].1,
+            CompressedChunk::Uniform((id(&assets, "Sand"), Vec2::ZERO))
+        );
+        let ids: Vec<usize> = save.chunks[1].1.runs().map(|run| run.cell.0).collect();
+        assert_eq!(ids, vec![0, id(&assets, "Water"), 0]);
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let assets = assets();
+        let sand = id(&assets, "Sand");
+        let mut world = CellWorld {
+            assets: assets.clone(),
+            ..Default::default()
+        };
+        world.insert(IVec2::new(5, 5), (sand, Vec2::ZERO));
+        world.insert(IVec2::new(-150, 30), (sand, Vec2::new(0.0, 2.0)));
+
+        let path = std::env::temp_dir().join(format!("save_round_trip_{}.ron", std::process::id()));
+        world.save(&path).expect("world saves");
+        let mut loaded = CellWorld {
+            assets,
+            ..Default::default()
+        };
+        let result = loaded.load(&path);
+        let _ = fs::remove_file(&path);
+        result.expect("world loads");
+
+        assert_eq!(loaded.get(IVec2::new(5, 5)), Some((sand, Vec2::ZERO)));
+        assert_eq!(
+            loaded.get(IVec2::new(-150, 30)),
+            Some((sand, Vec2::new(0.0, 2.0)))
+        );
+        assert_eq!(loaded.get(IVec2::new(6, 5)), Some((0, Vec2::ZERO)));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+
+use glam::{IVec2, Vec2};
+use hashbrown::{HashMap, HashSet};
+use log::{info, warn};
+
+use crate::{input::InputState, instance_data::InstanceData, utils::line_cells};
+
+use super::CellWorld;
+
+/// Most cells a single flood fill may change, filling open air in an
+/// endless world would never finish otherwise.
+pub const FLOOD_FILL_LIMIT: usize = 250_000;
+
+/// A set of cells to move, delete or convert as one.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    cells: HashSet<IVec2>,
+    outline: Vec<IVec2>,
+}
+
+impl Selection {
+    pub fn from_cells(cells: HashSet<IVec2>) -> Self {
+        let mut outline: Vec<_> = cells
+            .iter()
+            .filter(|pos| {
+                [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                    .iter()
+                    .any(|offset| !cells.contains(&(**pos + *offset)))
+            })
+            .copied()
+            .collect();
+        outline.sort_by_key(|pos| (pos.y, pos.x));
+        Self { cells, outline }
+    }
+
+    /// Every cell between the two corners, both included.
+    pub fn rect(a: IVec2, b: IVec2) -> Self {
+        let (min, max) = (a.min(b), a.max(b));
+        let mut cells = HashSet::new();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                cells.insert(IVec2::new(x, y));
+            }
+        }
+        Selection::from_cells(cells)
+    }
+
+    /// Cells inside the closed polygon through `points`, edges included.
+    pub fn lasso(points: &[IVec2]) -> Self {
+        let mut cells = HashSet::new();
+        let Some(first) = points.first() else {
+            return Selection::default();
+        };
+        let edges: Vec<(IVec2, IVec2)> = points
+            .iter()
+            .zip(points.iter().skip(1).chain(std::iter::once(first)))
+            .map(|(a, b)| (*a, *b))
+            .collect();
+        for (a, b) in edges.iter() {
+            cells.extend(line_cells(*a, *b));
+        }
+
+        let (min, max) = points
+            .iter()
+            .fold((*first, *first), |(min, max), p| (min.min(*p), max.max(*p)));
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                // Even-odd rule on the cell centre.
+                let point = Vec2::new(x as f32, y as f32);
+                let inside = edges
+                    .iter()
+                    .filter(|(a, b)| {
+                        let (a, b) = (a.as_vec2(), b.as_vec2());
+                        (a.y > point.y) != (b.y > point.y)
+                            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    })
+                    .count()
+                    % 2
+                    == 1;
+                if inside {
+                    cells.insert(IVec2::new(x, y));
+                }
+            }
+        }
+        Selection::from_cells(cells)
+    }
+
+    pub fn contains(&self, pos: IVec2) -> bool {
+        self.cells.contains(&pos)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.cells.iter().copied()
+    }
+
+    /// Cells on the edge of the selection, for drawing it.
+    pub fn outline(&self) -> &[IVec2] {
+        &self.outline
+    }
+
+    /// Smallest and largest cell, both included.
+    pub fn bounds(&self) -> Option<(IVec2, IVec2)> {
+        let mut cells = self.cells();
+        let first = cells.next()?;
+        Some(cells.fold((first, first), |(min, max), p| (min.min(p), max.max(p))))
+    }
+
+    pub fn translated(&self, offset: IVec2) -> Self {
+        Selection::from_cells(self.cells().map(|pos| pos + offset).collect())
+    }
+
+    /// Cells grouped by the chunk they live in, so edits touch each chunk
+    /// in one go.
+    pub fn cells_by_chunk(&self) -> Vec<(IVec2, Vec<IVec2>)> {
+        let mut chunks: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+        for pos in self.cells() {
+            chunks
+                .entry(CellWorld::calculate_chunk_pos(pos))
+                .or_default()
+                .push(pos);
+        }
+        let mut chunks: Vec<_> = chunks.into_iter().collect();
+        chunks.sort_by_key(|(chunk_pos, _)| (chunk_pos.x, chunk_pos.y));
+        chunks
+    }
+}
+
+/// Selection being dragged out in the editor. Only the corners or points
+/// are kept while dragging, the cells are selected on release.
+#[derive(Clone, Debug)]
+pub enum SelectionDrag {
+    Rect { start: IVec2, end: IVec2 },
+    Lasso(Vec<IVec2>),
+}
+
+impl SelectionDrag {
+    pub fn selection(&self) -> Selection {
+        match self {
+            SelectionDrag::Rect { start, end } => Selection::rect(*start, *end),
+            SelectionDrag::Lasso(points) => Selection::lasso(points),
+        }
+    }
+
+    /// Cells along the edges of the drag, for drawing it while it changes.
+    pub fn preview(&self) -> Vec<IVec2> {
+        let corners = match self {
+            SelectionDrag::Rect { start, end } => vec![
+                *start,
+                IVec2::new(end.x, start.y),
+                *end,
+                IVec2::new(start.x, end.y),
+            ],
+            SelectionDrag::Lasso(points) => points.clone(),
+        };
+        let Some(first) = corners.first() else {
+            return vec![];
+        };
+        corners
+            .iter()
+            .zip(corners.iter().skip(1).chain(std::iter::once(first)))
+            .flat_map(|(a, b)| line_cells(*a, *b))
+            .collect()
+    }
+}
+
+impl CellWorld {
+    /// Replaces the 4-connected region of the material at `start` with
+    /// `cell`, crossing chunk borders. Returns the number of cells changed.
+    pub fn flood_fill(&mut self, start: IVec2, cell: usize, limit: usize) -> usize {
+        let target = self.load_cell(start).map_or(0, |(id, _)| id);
+        if target == cell {
+            return 0;
+        }
+
+        let mut queue = VecDeque::from([start]);
+        let mut changed = 0;
+        while let Some(pos) = queue.pop_front() {
+            if self.load_cell(pos).map_or(0, |(id, _)| id) != target {
+                continue;
+            }
+            if changed == limit {
+                warn!("flood fill stopped after {} cells", limit);
+                break;
+            }
+            self.insert(pos, (cell, Vec2::ZERO));
+            changed += 1;
+            for offset in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                queue.push_back(pos + offset);
+            }
+        }
+        changed
+    }
+
+    /// Empties every selected cell. Returns the number of cells changed.
+    pub fn delete_selection(&mut self, selection: &Selection) -> usize {
+        self.fill_selection(selection, |_| Some(0))
+    }
+
+    /// Turns every non-empty selected cell into `cell`.
+    pub fn convert_selection(&mut self, selection: &Selection, cell: usize) -> usize {
+        self.fill_selection(selection, |current| (current != 0).then_some(cell))
+    }
+
+    /// Moves the selected cells by `offset`, overwriting whatever is at the
+    /// destination, and returns the moved selection.
+    pub fn move_selection(&mut self, selection: &Selection, offset: IVec2) -> Selection {
+        let mut moved = vec![];
+        for (_, cells) in selection.cells_by_chunk() {
+            for pos in cells {
+                let cell = self.load_cell(pos).map_or(0, |(id, _)| id);
+                moved.push((pos + offset, cell));
+            }
+        }
+        self.delete_selection(selection);
+        for (pos, cell) in moved {
+            if self.load_cell(pos).map_or(0, |(id, _)| id) != cell {
+                self.insert(pos, (cell, Vec2::ZERO));
+            }
+        }
+        selection.translated(offset)
+    }
+
+    /// Writes `f(current)` to each selected cell where it returns a new value.
+    fn fill_selection(
+        &mut self,
+        selection: &Selection,
+        f: impl Fn(usize) -> Option<usize>,
+    ) -> usize {
+        let mut changed = 0;
+        for (_, cells) in selection.cells_by_chunk() {
+            for pos in cells {
+                let current = self.load_cell(pos).map_or(0, |(id, _)| id);
+                match f(current) {
+                    Some(cell) if cell != current => {
+                        self.insert(pos, (cell, Vec2::ZERO));
+                        changed += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        changed
+    }
+
+    /// Editor handling for dragging out, moving and editing selections.
+    pub(crate) fn poll_selection_input(&mut self, input: &InputState) {
+        let cursor = input.cursor_world.as_ivec2();
+
+        let (moved, action) = match self.selection_drag.as_mut() {
+            None => {
+                self.selection_drag = if input.action_active("editor", "select_rect") {
+                    Some(SelectionDrag::Rect {
+                        start: cursor,
+                        end: cursor,
+                    })
+                } else if input.action_active("editor", "select_lasso") {
+                    Some(SelectionDrag::Lasso(vec![cursor]))
+                } else {
+                    return;
+                };
+                self.selection = None;
+                self.selection_revision += 1;
+                return;
+            }
+            Some(SelectionDrag::Rect { end, .. }) => {
+                let moved = *end != cursor;
+                *end = cursor;
+                (moved, "select_rect")
+            }
+            Some(SelectionDrag::Lasso(points)) => {
+                let moved = points.last() != Some(&cursor);
+                if moved {
+                    points.push(cursor);
+                }
+                (moved, "select_lasso")
+            }
+        };
+
+        if !input.action_down("editor", action) {
+            if let Some(drag) = self.selection_drag.take() {
+                let selection = drag.selection();
+                info!("selected {} cells", selection.len());
+                self.selection = Some(selection);
+            }
+            self.selection_revision += 1;
+        } else if moved {
+            self.selection_revision += 1;
+        }
+    }
+
+    /// Runs a one-shot selection action from the editor bindings.
+    pub(crate) fn selection_action(&mut self, action: &str, cursor: IVec2) {
+        if action == "flood_fill" {
+            let changed = self.flood_fill(cursor, self.selected as usize, FLOOD_FILL_LIMIT);
+            info!("flood filled {} cells", changed);
+            return;
+        }
+
+        let Some(selection) = self.selection.take() else {
+            return;
+        };
+        self.selection_revision += 1;
+        let offset = match action {
+            "selection_up" => IVec2::Y,
+            "selection_down" => IVec2::NEG_Y,
+            "selection_left" => IVec2::NEG_X,
+            "selection_right" => IVec2::X,
+            _ => IVec2::ZERO,
+        };
+        self.selection = match action {
+            "selection_delete" => {
+                self.delete_selection(&selection);
+                Some(selection)
+            }
+            "selection_convert" => {
+                self.convert_selection(&selection, self.selected as usize);
+                Some(selection)
+            }
+            "selection_clear" => None,
+            _ if offset != IVec2::ZERO => Some(self.move_selection(&selection, offset)),
+            _ => Some(selection),
+        };
+    }
+
+    pub(crate) fn render_selection(&self) -> Vec<InstanceData> {
+        let preview = self.selection_drag.as_ref().map(SelectionDrag::preview);
+        self.selection
+            .iter()
+            .flat_map(|selection| selection.outline())
+            .chain(preview.iter().flatten())
+            .map(|pos| InstanceData {
+                position: pos.as_vec2(),
+                color: 0,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+
+    use crate::objects::sand::brush::BrushMode;
+
+    use super::*;
+
+    const STONE: usize = 2;
+    const SAND: usize = 7;
+
+    /// Stone walls around `min..=max`, the inside left empty.
+    fn walled(min: IVec2, max: IVec2) -> CellWorld {
+        let mut world = CellWorld::default();
+        let size = (max - min).as_uvec2() + UVec2::ONE;
+        for (corner, size) in [
+            (min, UVec2::new(size.x, 1)),
+            (IVec2::new(min.x, max.y), UVec2::new(size.x, 1)),
+            (min, UVec2::new(1, size.y)),
+            (IVec2::new(max.x, min.y), UVec2::new(1, size.y)),
+        ] {
+            world.fill_rect(corner, size, STONE, BrushMode::Overwrite);
+        }
+        world
+    }
+
+    #[test]
+    fn flood_fill_stays_inside_walls_across_chunks() {
+        let mut world = walled(IVec2::new(95, -5), IVec2::new(105, 5));
+        assert_eq!(
+            world.flood_fill(IVec2::new(100, 0), SAND, FLOOD_FILL_LIMIT),
+            81
+        );
+        assert_eq!(world.get(IVec2::new(99, 0)), Some((SAND, Vec2::ZERO)));
+        assert_eq!(world.get(IVec2::new(95, 0)), Some((STONE, Vec2::ZERO)));
+        assert_eq!(world.get(IVec2::new(106, 0)), Some((0, Vec2::ZERO)));
+        assert_eq!(
+            world.flood_fill(IVec2::new(100, 0), SAND, FLOOD_FILL_LIMIT),
+            0
+        );
+    }
+
+    #[test]
+    fn flood_fill_stops_at_limit() {
+        let mut world = CellWorld::default();
+        assert_eq!(world.flood_fill(IVec2::ZERO, SAND, 100), 100);
+    }
+
+    #[test]
+    fn rect_bounds_and_outline() {
+        let selection = Selection::rect(IVec2::new(4, 2), IVec2::new(-1, -2));
+        assert_eq!(selection.len(), 30);
+        assert_eq!(
+            selection.bounds(),
+            Some((IVec2::new(-1, -2), IVec2::new(4, 2)))
+        );
+        assert_eq!(selection.outline().len(), 18);
+        assert_eq!(Selection::default().bounds(), None);
+        assert_eq!(
+            selection.translated(IVec2::X).bounds(),
+            Some((IVec2::new(0, -2), IVec2::new(5, 2)))
+        );
+    }
+
+    #[test]
+    fn lasso_fills_polygon() {
+        let selection = Selection::lasso(&[IVec2::ZERO, IVec2::new(10, 0), IVec2::new(0, 10)]);
+        assert_eq!(selection.bounds(), Some((IVec2::ZERO, IVec2::new(10, 10))));
+        assert!(selection.contains(IVec2::new(2, 2)));
+        assert!(selection.contains(IVec2::new(5, 5)));
+        assert!(!selection.contains(IVec2::new(8, 8)));
+        assert!(Selection::lasso(&[]).is_empty());
+    }
+
+    #[test]
+    fn drag_previews_edges_and_selects_on_release() {
+        let drag = SelectionDrag::Rect {
+            start: IVec2::new(3, 3),
+            end: IVec2::ZERO,
+        };
+        let preview: HashSet<IVec2> = drag.preview().into_iter().collect();
+        let selection = drag.selection();
+        assert_eq!(preview, selection.outline().iter().copied().collect());
+        assert_eq!(selection.len(), 16);
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{IVec2, UVec2, Vec2};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{selection::Selection, CellWorld};
+
+pub const STAMP_VERSION: u32 = 1;
+
+/// Folder under the asset root holding the stamp library.
+pub const STAMP_DIR: &str = "stamps";
+
+/// A rectangle of cells that can be pasted back into a world, like a house
+/// or a tree. Empty cells are see-through when pasting.
+///
+/// Cells are indices into `materials` plus one, 0 is empty, stored row by
+/// row starting at the bottom, so stamps survive changes to the asset list.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub version: u32,
+    pub name: String,
+    pub size: UVec2,
+    pub materials: Vec<String>,
+    pub cells: Vec<u16>,
+}
+
+impl Stamp {
+    fn index(&self, pos: UVec2) -> usize {
+        (pos.y * self.size.x + pos.x) as usize
+    }
+
+    /// Material at `pos`, measured from the bottom left corner.
+    pub fn get(&self, pos: UVec2) -> Option<&str> {
+        if pos.x >= self.size.x || pos.y >= self.size.y {
+            return None;
+        }
+        match self.cells[self.index(pos)] {
+            0 => None,
+            cell => self.materials.get(cell as usize - 1).map(String::as_str),
+        }
+    }
+
+    /// Every non-empty cell with its material name, for generators that
+    /// place stamps themselves.
+    pub fn cells(&self) -> impl Iterator<Item = (UVec2, &str)> + '_ {
+        (0..self.size.y)
+            .flat_map(move |y| (0..self.size.x).map(move |x| UVec2::new(x, y)))
+            .filter_map(|pos| self.get(pos).map(|material| (pos, material)))
+    }
+
+    /// Builds a stamp of the same materials with cells moved by `f`, which
+    /// gets the old position and returns the new one in a `size` rectangle.
+    fn remap(&self, size: UVec2, f: impl Fn(UVec2) -> UVec2) -> Self {
+        let mut stamp = Stamp {
+            size,
+            cells: vec![0; self.cells.len()],
+            ..self.clone()
+        };
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let from = UVec2::new(x, y);
+                let to = stamp.index(f(from));
+                stamp.cells[to] = self.cells[self.index(from)];
+            }
+        }
+        stamp
+    }
+
+    /// Quarter turn clockwise.
+    pub fn rotated_cw(&self) -> Self {
+        let size = self.size;
+        self.remap(UVec2::new(size.y, size.x), |pos| {
+            UVec2::new(pos.y, size.x - 1 - pos.x)
+        })
+    }
+
+    /// Flipped left to right.
+    pub fn mirrored_x(&self) -> Self {
+        let size = self.size;
+        self.remap(size, |pos| UVec2::new(size.x - 1 - pos.x, pos.y))
+    }
+
+    /// Flipped upside down.
+    pub fn mirrored_y(&self) -> Self {
+        let size = self.size;
+        self.remap(size, |pos| UVec2::new(pos.x, size.y - 1 - pos.y))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        let stamp: Stamp =
+            ron::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if stamp.version != STAMP_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported stamp version {}, expected {}",
+                    stamp.version, STAMP_VERSION
+                ),
+            ));
+        }
+        if stamp.cells.len() != (stamp.size.x * stamp.size.y) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "stamp has {} cells, expected {}x{}",
+                    stamp.cells.len(),
+                    stamp.size.x,
+                    stamp.size.y
+                ),
+            ));
+        }
+        Ok(stamp)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default().compact_arrays(true),
+        )
+        .map_err(io::Error::other)?;
+        fs::write(path.as_ref(), contents)
+    }
+}
+
+/// Stamps found in a folder, sorted by name.
+#[derive(Debug, Default)]
+pub struct StampLibrary {
+    pub dir: Option<PathBuf>,
+    pub stamps: Vec<Stamp>,
+}
+
+impl StampLibrary {
+    /// Loads every `.ron` file in `dir`. A missing folder gives an empty
+    /// library that stamps can still be saved into.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Self {
+        let mut stamps = vec![];
+        if let Ok(entries) = fs::read_dir(dir.as_ref()) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.extension().is_some_and(|ext| ext == "ron") {
+                    match Stamp::load(&path) {
+                        Ok(stamp) => stamps.push(stamp),
+                        Err(e) => warn!("couldn't load stamp {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+        stamps.sort_by(|a, b| a.name.cmp(&b.name));
+        info!(
+            "loaded {} stamps from {}",
+            stamps.len(),
+            dir.as_ref().display()
+        );
+        Self {
+            dir: Some(dir.as_ref().to_path_buf()),
+            stamps,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stamp> {
+        self.stamps.iter().find(|stamp| stamp.name == name)
+    }
+
+    /// Saves `stamp` as `<name>.ron` in the library folder and adds it.
+    /// Fails if the library already has a stamp of that name.
+    pub fn add(&mut self, stamp: Stamp) -> io::Result<PathBuf> {
+        let dir = self.dir.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "stamp library has no folder")
+        })?;
+        if self.get(&stamp.name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("stamp library already has {}", stamp.name),
+            ));
+        }
+        let path = dir.join(format!("{}.ron", stamp.name));
+        stamp.save(&path)?;
+        self.stamps.push(stamp);
+        self.stamps.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(path)
+    }
+
+    /// First `stamp_<n>` name that isn't taken.
+    pub fn next_free_name(&self) -> String {
+        (1..)
+            .map(|n| format!("stamp_{}", n))
+            .find(|name| self.get(name).is_none())
+            .unwrap_or_default()
+    }
+}
+
+impl CellWorld {
+    /// Copies the selected cells into a stamp covering the selection bounds.
+    pub fn copy_stamp(&mut self, selection: &Selection, name: &str) -> Option<Stamp> {
+        let (min, max) = selection.bounds()?;
+        let size = (max - min + IVec2::ONE).as_uvec2();
+        let mut stamp = Stamp {
+            version: STAMP_VERSION,
+            name: name.to_string(),
+            size,
+            materials: vec![],
+            cells: vec![0; (size.x * size.y) as usize],
+        };
+        let mut palette: Vec<usize> = vec![];
+
+        for (_, cells) in selection.cells_by_chunk() {
+            for pos in cells {
+                let cell = self.load_cell(pos).map_or(0, |(id, _)| id);
+                let Some(asset) = cell.checked_sub(1).and_then(|id| self.assets.get(id)) else {
+                    continue;
+                };
+                let index = match palette.iter().position(|id| *id == cell) {
+                    Some(index) => index,
+                    None => {
+                        palette.push(cell);
+                        stamp.materials.push(asset.name);
+                        palette.len() - 1
+                    }
+                };
+                let i = stamp.index((pos - min).as_uvec2());
+                stamp.cells[i] = index as u16 + 1;
+            }
+        }
+        Some(stamp)
+    }
+
+    /// Pastes `stamp` with its bottom left corner at `min`, skipping empty
+    /// cells. Returns the number of cells written.
+    pub fn paste_stamp(&mut self, stamp: &Stamp, min: IVec2) -> usize {
+        let materials: Vec<Option<usize>> = stamp
+            .materials
+            .iter()
+            .map(|name| {
+                let id = self.assets.get_index_by_name(name.clone()).map(|i| i + 1);
+                if id.is_none() {
+                    warn!("stamp {} uses unknown material {}", stamp.name, name);
+                }
+                id
+            })
+            .collect();
+
+        let mut written = 0;
+        for y in 0..stamp.size.y {
+            for x in 0..stamp.size.x {
+                let local = UVec2::new(x, y);
+                let cell = stamp.cells[stamp.index(local)];
+                let Some(id) = (cell as usize)
+                    .checked_sub(1)
+                    .and_then(|i| materials.get(i).copied().flatten())
+                else {
+                    continue;
+                };
+                self.insert(min + local.as_ivec2(), (id, Vec2::ZERO));
+                written += 1;
+            }
+        }
+        written
+    }
+
+    /// Runs a clipboard or library action from the editor bindings.
+    pub(crate) fn stamp_action(&mut self, action: &str, cursor: IVec2) {
+        match action {
+            "copy" | "cut" => {
+                let Some(selection) = self.selection.clone() else {
+                    return;
+                };
+                let name = self.stamps.next_free_name();
+                self.clipboard = self.copy_stamp(&selection, &name);
+                if action == "cut" {
+                    self.delete_selection(&selection);
+                }
+            }
+            "paste" => {
+                if let Some(stamp) = self.clipboard.clone() {
+                    let written = self.paste_stamp(&stamp, cursor);
+                    info!("pasted {} cells of {}", written, stamp.name);
+                }
+            }
+            "stamp_rotate" => self.clipboard = self.clipboard.as_ref().map(Stamp::rotated_cw),
+            "stamp_mirror" => self.clipboard = self.clipboard.as_ref().map(Stamp::mirrored_x),
+            "stamp_flip" => self.clipboard = self.clipboard.as_ref().map(Stamp::mirrored_y),
+            "stamp_save" => {
+                let Some(mut stamp) = self.clipboard.clone() else {
+                    return;
+                };
+                match self.stamps.get(&stamp.name) {
+                    Some(saved) if *saved == stamp => {
+                        info!("stamp {} is already saved", stamp.name);
+                        return;
+                    }
+                    // Rotated or mirrored library stamps keep their name,
+                    // save them alongside the original.
+                    Some(_) => stamp.name = self.stamps.next_free_name(),
+                    None => {}
+                }
+                self.clipboard = Some(stamp.clone());
+                match self.stamps.add(stamp) {
+                    Ok(path) => info!("saved stamp to {}", path.display()),
+                    Err(e) => warn!("couldn't save stamp: {}", e),
+                }
+            }
+            "stamp_next" => {
+                let library = &self.stamps.stamps;
+                if library.is_empty() {
+                    return;
+                }
+                let next = match &self.clipboard {
+                    Some(current) => library
+                        .iter()
+                        .position(|stamp| stamp.name == current.name)
+                        .map_or(0, |i| (i + 1) % library.len()),
+                    None => 0,
+                };
+                self.clipboard = Some(library[next].clone());
+                info!("picked stamp {}", library[next].name);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enums::cell_assets::import_assets;
+
+    use super::*;
+
+    /// An L of two materials, 2 wide and 3 tall:
+    ///
+    /// ```text
+    /// a .
+    /// a .
+    /// a b
+    /// ```
+    fn l_shape() -> Stamp {
+        Stamp {
+            version: STAMP_VERSION,
+            name: "l".to_string(),
+            size: UVec2::new(2, 3),
+            materials: vec!["Stone".to_string(), "Sand".to_string()],
+            cells: vec![1, 2, 1, 0, 1, 0],
+        }
+    }
+
+    fn world() -> CellWorld {
+        CellWorld {
+            assets: import_assets(Path::new("assets")).expect("assets load"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rotate_quarter_turn() {
+        let stamp = l_shape();
+        let rotated = stamp.rotated_cw();
+        assert_eq!(rotated.size, UVec2::new(3, 2));
+        // a a a
+        // b . .
+        assert_eq!(rotated.get(UVec2::new(0, 0)), Some("Sand"));
+        assert_eq!(rotated.get(UVec2::new(2, 1)), Some("Stone"));
+        assert_eq!(rotated.get(UVec2::new(2, 0)), None);
+        let turned = rotated.rotated_cw().rotated_cw().rotated_cw();
+        assert_eq!(turned, stamp);
+    }
+
+    #[test]
+    fn mirror_and_flip() {
+        let stamp = l_shape();
+        let mirrored = stamp.mirrored_x();
+        assert_eq!(mirrored.get(UVec2::new(0, 0)), Some("Sand"));
+        assert_eq!(mirrored.get(UVec2::new(1, 2)), Some("Stone"));
+        assert_eq!(mirrored.mirrored_x(), stamp);
+
+        let flipped = stamp.mirrored_y();
+        assert_eq!(flipped.get(UVec2::new(1, 2)), Some("Sand"));
+        assert_eq!(flipped.get(UVec2::new(1, 0)), None);
+        assert_eq!(flipped.mirrored_y(), stamp);
+    }
+
+    #[test]
+    fn copy_paste_round_trip() {
+        let mut world = world();
+        let stamp = l_shape();
+        assert_eq!(world.paste_stamp(&stamp, IVec2::new(98, 10)), 4);
+
+        let selection = Selection::rect(IVec2::new(98, 10), IVec2::new(99, 12));
+        let copied = world
+            .copy_stamp(&selection, "copy")
+            .expect("selection has bounds");
+        assert_eq!(copied.size, stamp.size);
+        assert_eq!(
+            copied.cells().collect::<Vec<_>>(),
+            stamp.cells().collect::<Vec<_>>()
+        );
+
+        world.paste_stamp(&copied, IVec2::new(-5, -5));
+        let stone = world.assets.get_index_by_name("Stone".to_string()).unwrap() + 1;
+        assert_eq!(world.get(IVec2::new(-5, -3)), Some((stone, Vec2::ZERO)));
+        assert_eq!(world.get(IVec2::new(-4, -3)), Some((0, Vec2::ZERO)));
+    }
+
+    #[test]
+    fn saving_a_transformed_stamp_keeps_the_original() {
+        let dir = std::env::temp_dir().join(format!("stamp_library_{}", std::process::id()));
+        let mut world = world();
+        world.stamps = StampLibrary {
+            dir: Some(dir.clone()),
+            stamps: vec![],
+        };
+        world.stamps.add(l_shape()).expect("stamp saves");
+        assert!(world.stamps.add(l_shape()).is_err());
+
+        world.clipboard = Some(l_shape());
+        world.stamp_action("stamp_rotate", IVec2::ZERO);
+        world.stamp_action("stamp_save", IVec2::ZERO);
+        world.stamp_action("stamp_save", IVec2::ZERO);
+
+        let result = Stamp::load(dir.join("l.ron"));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(result.expect("original still on disk"), l_shape());
+        assert_eq!(world.stamps.get("l"), Some(&l_shape()));
+        let rotated = world.stamps.get("stamp_1").expect("saved under a new name");
+        assert_eq!(rotated.cells, l_shape().rotated_cw().cells);
+        assert_eq!(world.stamps.stamps.len(), 2);
+    }
+}
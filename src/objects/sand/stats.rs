@@ -0,0 +1,76 @@
+use std::io;
+
+use glam::{IVec2, Vec2};
+use hashbrown::HashMap;
+
+use super::{compression::CompressedChunk, CellWorld};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+impl CellWorld {
+    /// Every chunk in the world, awake, sleeping or unloaded, in compressed
+    /// form and sorted by position.
+    pub fn snapshot_chunks(&self) -> io::Result<Vec<(IVec2, CompressedChunk)>> {
+        let mut chunks: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|(pos, chunk)| (*pos, CompressedChunk::compress(chunk)))
+            .chain(
+                self.compressed_chunks
+                    .iter()
+                    .map(|(pos, chunk)| (*pos, chunk.clone())),
+            )
+            .collect();
+        for chunk_pos in self.unloaded_chunks.positions() {
+            if let Some(chunk) = self.unloaded_chunks.read(chunk_pos)? {
+                chunks.push((chunk_pos, chunk));
+            }
+        }
+        chunks.sort_by_key(|(pos, _)| (pos.x, pos.y));
+        Ok(chunks)
+    }
+
+    /// Stable FNV-1a hash of the cell contents and shades. Empty chunks are
+    /// skipped and uniform ones hashed as a single run, so the hash doesn't
+    /// depend on which chunks happen to be allocated or asleep.
+    pub fn state_hash(&self) -> io::Result<u64> {
+        let mut hash = FNV_OFFSET;
+        for (chunk_pos, chunk) in self.snapshot_chunks()? {
+            if chunk == CompressedChunk::Uniform((0, Vec2::ZERO)) {
+                continue;
+            }
+            hash = fnv(hash, &chunk_pos.x.to_le_bytes());
+            hash = fnv(hash, &chunk_pos.y.to_le_bytes());
+            for run in chunk.runs() {
+                hash = fnv(hash, &run.len.to_le_bytes());
+                hash = fnv(hash, &(run.cell.0 as u64).to_le_bytes());
+                hash = fnv(hash, &run.cell.1.x.to_bits().to_le_bytes());
+                hash = fnv(hash, &run.cell.1.y.to_bits().to_le_bytes());
+            }
+            for (shade, len) in chunk.shade_runs() {
+                hash = fnv(hash, &len.to_le_bytes());
+                hash = fnv(hash, &[shade]);
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Number of cells of each asset id, empty cells excluded.
+    pub fn cell_counts(&self) -> io::Result<HashMap<usize, usize>> {
+        let mut counts = HashMap::new();
+        for (_, chunk) in self.snapshot_chunks()? {
+            for run in chunk.runs() {
+                *counts.entry(run.cell.0).or_insert(0) += run.len as usize;
+            }
+        }
+        counts.remove(&0);
+        Ok(counts)
+    }
+}
@@ -0,0 +1,304 @@
+use std::{fs, io, path::PathBuf};
+
+use glam::IVec2;
+use hashbrown::{HashMap, HashSet};
+use log::{info, warn};
+use serde::Deserialize;
+
+use super::{compression::CompressedChunk, CellWorld};
+
+/// Radii are measured in chunks around the chunk containing the focus point.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StreamingConfig {
+    pub simulation_radius: i32,
+    pub render_radius: i32,
+    /// Chunks further away than this are evicted to the `ChunkStore`.
+    /// Keep it above `render_radius` so chunks don't thrash on the edge.
+    pub unload_radius: i32,
+    /// Evict to this directory instead of keeping chunks compressed in memory.
+    pub storage_dir: Option<PathBuf>,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            simulation_radius: 4,
+            render_radius: 6,
+            unload_radius: 8,
+            storage_dir: None,
+        }
+    }
+}
+
+/// Where chunks outside of `StreamingConfig::unload_radius` live.
+pub enum ChunkStore {
+    Memory(HashMap<IVec2, CompressedChunk>),
+    /// Chunk files are only valid for the asset ids of the current session,
+    /// use `CellWorld::save` for anything that has to outlive it.
+    Disk {
+        dir: PathBuf,
+        stored: HashSet<IVec2>,
+    },
+}
+
+impl Default for ChunkStore {
+    fn default() -> Self {
+        ChunkStore::Memory(HashMap::new())
+    }
+}
+
+impl ChunkStore {
+    pub fn from_config(config: &StreamingConfig) -> Self {
+        match &config.storage_dir {
+            Some(dir) => ChunkStore::Disk {
+                dir: dir.clone(),
+                stored: HashSet::new(),
+            },
+            None => ChunkStore::default(),
+        }
+    }
+
+    fn chunk_path(dir: &std::path::Path, chunk_pos: IVec2) -> PathBuf {
+        dir.join(format!("chunk_{}_{}.ron", chunk_pos.x, chunk_pos.y))
+    }
+
+    pub fn contains(&self, chunk_pos: IVec2) -> bool {
+        match self {
+            ChunkStore::Memory(chunks) => chunks.contains_key(&chunk_pos),
+            ChunkStore::Disk { stored, .. } => stored.contains(&chunk_pos),
+        }
+    }
+
+    pub fn positions(&self) -> Vec<IVec2> {
+        match self {
+            ChunkStore::Memory(chunks) => chunks.keys().copied().collect(),
+            ChunkStore::Disk { stored, .. } => stored.iter().copied().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ChunkStore::Memory(chunks) => chunks.len(),
+            ChunkStore::Disk { stored, .. } => stored.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn store(&mut self, chunk_pos: IVec2, chunk: CompressedChunk) -> io::Result<()> {
+        match self {
+            ChunkStore::Memory(chunks) => {
+                chunks.insert(chunk_pos, chunk);
+            }
+            ChunkStore::Disk { dir, stored } => {
+                fs::create_dir_all(&*dir)?;
+                let contents = ron::to_string(&chunk).map_err(io::Error::other)?;
+                fs::write(ChunkStore::chunk_path(dir, chunk_pos), contents)?;
+                stored.insert(chunk_pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a stored chunk without removing it from the store.
+    pub fn read(&self, chunk_pos: IVec2) -> io::Result<Option<CompressedChunk>> {
+        match self {
+            ChunkStore::Memory(chunks) => Ok(chunks.get(&chunk_pos).cloned()),
+            ChunkStore::Disk { dir, stored } => {
+                if !stored.contains(&chunk_pos) {
+                    return Ok(None);
+                }
+                let contents = fs::read_to_string(ChunkStore::chunk_path(dir, chunk_pos))?;
+                ron::from_str(&contents)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    pub fn take(&mut self, chunk_pos: IVec2) -> io::Result<Option<CompressedChunk>> {
+        let chunk = self.read(chunk_pos)?;
+        match self {
+            ChunkStore::Memory(chunks) => {
+                chunks.remove(&chunk_pos);
+            }
+            ChunkStore::Disk { dir, stored } => {
+                if stored.remove(&chunk_pos) {
+                    fs::remove_file(ChunkStore::chunk_path(dir, chunk_pos))?;
+                }
+            }
+        }
+        Ok(chunk)
+    }
+
+    pub fn clear(&mut self) {
+        for chunk_pos in self.positions() {
+            if let Err(e) = self.take(chunk_pos) {
+                warn!("couldn't remove stored chunk {}: {}", chunk_pos, e);
+            }
+        }
+    }
+}
+
+impl CellWorld {
+    pub fn with_streaming(mut self, config: StreamingConfig) -> Self {
+        self.unloaded_chunks = ChunkStore::from_config(&config);
+        self.streaming = config;
+        self
+    }
+
+    pub fn focus_chunk(&self) -> IVec2 {
+        CellWorld::calculate_chunk_pos(self.focus.floor().as_ivec2())
+    }
+
+    /// Chebyshev distance in chunks between `chunk_pos` and the focus chunk.
+    pub fn chunk_distance(&self, chunk_pos: IVec2) -> i32 {
+        (chunk_pos - self.focus_chunk()).abs().max_element()
+    }
+
+    pub fn is_chunk_simulated(&self, chunk_pos: IVec2) -> bool {
+        self.chunk_distance(chunk_pos) <= self.streaming.simulation_radius
+    }
+
+    pub fn is_chunk_rendered(&self, chunk_pos: IVec2) -> bool {
+        self.chunk_distance(chunk_pos) <= self.streaming.render_radius
+    }
+
+    /// Evicts chunks past `unload_radius` and brings stored chunks within
+    /// `render_radius` back as sleeping chunks, generating any that are missing.
+    pub fn stream_chunks(&mut self) {
+        let focus_chunk = self.focus_chunk();
+        if self.last_focus_chunk == Some(focus_chunk) {
+            return;
+        }
+        self.last_focus_chunk = Some(focus_chunk);
+
+        let to_unload: Vec<IVec2> = self
+            .chunks
+            .keys()
+            .chain(self.compressed_chunks.keys())
+            .filter(|pos| self.chunk_distance(**pos) > self.streaming.unload_radius)
+            .copied()
+            .collect();
+
+        for chunk_pos in to_unload.iter() {
+            let compressed = match self.chunks.remove(chunk_pos) {
+                Some(chunk) => CompressedChunk::compress(&chunk),
+                None => match self.compressed_chunks.remove(chunk_pos) {
+                    Some(chunk) => chunk,
+                    None => continue,
+                },
+            };
+            if let Err(e) = self.unloaded_chunks.store(*chunk_pos, compressed.clone()) {
+                warn!("couldn't unload chunk {}: {}", chunk_pos, e);
+                self.compressed_chunks.insert(*chunk_pos, compressed);
+            }
+        }
+
+        let to_load: Vec<IVec2> = self
+            .unloaded_chunks
+            .positions()
+            .into_iter()
+            .filter(|pos| self.is_chunk_rendered(*pos))
+            .collect();
+
+        for chunk_pos in to_load.iter() {
+            match self.unloaded_chunks.take(*chunk_pos) {
+                Ok(Some(chunk)) => {
+                    self.compressed_chunks.insert(*chunk_pos, chunk);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("couldn't load chunk {}: {}", chunk_pos, e),
+            }
+        }
+
+        let generated = self.generate_around(focus_chunk, self.streaming.render_radius);
+
+        if !to_unload.is_empty() || !to_load.is_empty() || generated > 0 {
+            info!(
+                "streaming around {}: unloaded {}, loaded {}, generated {}, {} chunks stored",
+                focus_chunk,
+                to_unload.len(),
+                to_load.len(),
+                generated,
+                self.unloaded_chunks.len()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+
+    const FAR_CELL: IVec2 = IVec2::new(550, 50);
+    const SAND: (usize, Vec2) = (7, Vec2::ZERO);
+
+    fn world(storage_dir: Option<PathBuf>) -> CellWorld {
+        let mut world = CellWorld::default().with_streaming(StreamingConfig {
+            simulation_radius: 1,
+            render_radius: 1,
+            unload_radius: 2,
+            storage_dir,
+        });
+        world.insert(FAR_CELL, SAND);
+        world
+    }
+
+    /// Streams around the origin, then around `FAR_CELL` again.
+    fn unload_and_reload(world: &mut CellWorld) {
+        let far_chunk = CellWorld::calculate_chunk_pos(FAR_CELL);
+
+        world.stream_chunks();
+        assert!(world.unloaded_chunks.contains(far_chunk));
+        assert!(!world.chunks.contains_key(&far_chunk));
+        assert!(!world.compressed_chunks.contains_key(&far_chunk));
+        assert_eq!(world.get(FAR_CELL), None);
+
+        world.focus = FAR_CELL.as_vec2();
+        world.stream_chunks();
+        assert!(!world.unloaded_chunks.contains(far_chunk));
+        assert!(world.unloaded_chunks.is_empty());
+        assert!(world.compressed_chunks.contains_key(&far_chunk));
+        assert_eq!(world.get(FAR_CELL), Some(SAND));
+    }
+
+    #[test]
+    fn unloads_and_reloads_in_memory() {
+        let mut world = world(None);
+        unload_and_reload(&mut world);
+    }
+
+    #[test]
+    fn unloads_and_reloads_from_disk() {
+        let dir = std::env::temp_dir().join(format!("chunk_store_{}", std::process::id()));
+        let mut world = world(Some(dir.clone()));
+        let far_chunk = CellWorld::calculate_chunk_pos(FAR_CELL);
+
+        world.stream_chunks();
+        assert!(ChunkStore::chunk_path(&dir, far_chunk).exists());
+
+        world.last_focus_chunk = None;
+        world.focus = Vec2::ZERO;
+        unload_and_reload(&mut world);
+        assert!(!ChunkStore::chunk_path(&dir, far_chunk).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn store_take_round_trip() {
+        let mut store = ChunkStore::default();
+        let chunk = CompressedChunk::Uniform(SAND);
+        store.store(IVec2::ONE, chunk.clone()).expect("stores");
+        assert_eq!(store.read(IVec2::ONE).expect("reads"), Some(chunk.clone()));
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.take(IVec2::ONE).expect("takes"), Some(chunk));
+        assert!(store.is_empty());
+        assert_eq!(store.take(IVec2::ONE).expect("takes"), None);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{io, path::Path};
+
+use glam::Vec2;
+use log::info;
+use wgpu::TextureFormat;
+
+use crate::{
+    camera::Camera,
+    capture,
+    engine_config::EngineConfig,
+    enums::cell_assets::CellAssets,
+    image::RgbaImage,
+    renderer::{self, Renderer},
+    world::World,
+};
+
+/// Format frames are rendered and read back in, matching the window surface.
+pub const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// Renders `World` frames into a texture and reads them back, without a
+/// window. Falls back to a software adapter, so it also runs on CI machines
+/// without a GPU.
+pub struct OffscreenRenderer {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub adapter_info: wgpu::AdapterInfo,
+    pub renderer: Renderer,
+    pub camera: Camera,
+    pub engine_config: EngineConfig,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+}
+
+impl OffscreenRenderer {
+    pub async fn new(
+        engine_config: EngineConfig,
+        assets: &CellAssets,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err(format!(
+                "render size {}x{} must be at least 1x1",
+                width, height
+            ));
+        }
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: renderer::backends(),
+            ..Default::default()
+        });
+        let adapter =
+            renderer::request_adapter(&instance, None, engine_config.force_fallback_adapter)
+                .await?;
+        let adapter_info = adapter.get_info();
+        info!("{:?}", adapter_info);
+        let (device, queue) = renderer::request_device(&adapter).await?;
+
+        let (renderer, camera) = Renderer::new(
+            &device,
+            &queue,
+            OFFSCREEN_FORMAT,
+            width,
+            height,
+            assets,
+            &engine_config,
+        );
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut offscreen = Self {
+            device,
+            queue,
+            adapter_info,
+            renderer,
+            camera,
+            engine_config,
+            target,
+            target_view,
+        };
+        offscreen.look_at(Vec2::ZERO, height as f32);
+        Ok(offscreen)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.target.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.target.height()
+    }
+
+    /// Centres the camera on `center`, showing `height` cells from the bottom
+    /// to the top of the frame.
+    pub fn look_at(&mut self, center: Vec2, height: f32) {
+        // The view is `1 + zoom_factor` cells high.
+        self.camera.zoom_factor = (height - 1.0).max(0.01);
+        self.camera.position = center * self.camera.zoom_factor;
+        self.camera.update_matrix();
+    }
+
+    /// Draws `world` and reads the frame back, rows top to bottom. Objects
+    /// are drawn at their latest tick.
+    pub fn render(&mut self, world: &mut World) -> Result<RgbaImage, String> {
+        world.set_view(self.camera.visible_rect());
+        let size = [self.width() as f32, self.height() as f32];
+        self.renderer.render(
+            &self.device,
+            &self.queue,
+            world,
+            &mut self.camera,
+            1.0,
+            &self.engine_config.lighting,
+            &self.target_view,
+            size,
+        );
+        capture::read_texture(&self.device, &self.queue, &self.target)
+    }
+
+    pub fn render_png(&mut self, world: &mut World, path: impl AsRef<Path>) -> io::Result<()> {
+        self.render(world).map_err(io::Error::other)?.save_png(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        enums::{cell_assets::import_assets, CHUNK_SIZE},
+        objects::sand::streaming::StreamingConfig,
+        world::WorldObject,
+    };
+
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    fn render_seeded_world() {
+        let engine_config = EngineConfig {
+            force_fallback_adapter: true,
+            seed: Some(7),
+            streaming: StreamingConfig {
+                simulation_radius: 1,
+                render_radius: 1,
+                unload_radius: 2,
+                storage_dir: None,
+            },
+            ..Default::default()
+        };
+        let assets = import_assets(&engine_config.asset_root).expect("assets load");
+        let mut cell_world = World::init_cell_world(&assets, &engine_config).expect("world loads");
+        // The surface is close to height 0, so the frame shows sky and ground.
+        let center = Vec2::new(CHUNK_SIZE.x as f32 / 2.0, 0.0);
+        cell_world.set_focus(center);
+        cell_world.update(0.0);
+
+        let mut offscreen =
+            match pollster::block_on(OffscreenRenderer::new(engine_config, &assets, SIZE, SIZE)) {
+                Ok(offscreen) => offscreen,
+                Err(e) => {
+                    eprintln!("skipping, no graphics adapter: {}", e);
+                    return;
+                }
+            };
+        offscreen.look_at(center, 2.0 * CHUNK_SIZE.y as f32);
+        let mut world = World {
+            assets,
+            ..Default::default()
+        };
+        world.add_obj(Box::new(cell_world));
+
+        let frame = offscreen.render(&mut world).expect("frame reads back");
+        assert_eq!((frame.width, frame.height), (SIZE, SIZE));
+        let is_black = |pixel: &[u8; 4]| pixel[..3] == [0, 0, 0];
+        let (top, bottom) = frame.pixels.split_at(frame.pixels.len() / 2);
+        assert!(top[..SIZE as usize].iter().all(is_black), "sky isn't empty");
+        assert!(!bottom.iter().all(is_black), "ground wasn't drawn");
+    }
+
+    #[test]
+    fn renders_seeded_world() {
+        // Chunks are built on the stack, which debug builds need more of than
+        // test threads get.
+        thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(render_seeded_world)
+            .expect("render thread starts")
+            .join()
+            .expect("render thread finishes");
+    }
+}
@@ -0,0 +1,737 @@
+use std::{fs, path::Path};
+
+use bytemuck::{Pod, Zeroable};
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::Deserialize;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, RenderPipeline, Texture, TextureFormat, TextureView,
+};
+
+use crate::image::RgbaImage;
+
+/// Format the scene is drawn in before post-processing, so emissive cells
+/// can go past 1.0 and bloom.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BloomSettings {
+    /// Brightness above which pixels bloom. Lit materials reach 1.0 at
+    /// most, so above that only the glow of emissive cells blooms.
+    pub threshold: f32,
+    /// Range below the threshold that blooms partially.
+    pub knee: f32,
+    pub intensity: f32,
+    /// Blur spread in half resolution texels.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.1,
+            knee: 0.1,
+            intensity: 0.8,
+            radius: 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TonemapSettings {
+    pub exposure: f32,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self { exposure: 1.0 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ColorGradeSettings {
+    /// PNG relative to the assets folder, a strip of N slices of N x N.
+    pub lut: String,
+    /// Blend between the original (0.0) and graded (1.0) colours.
+    pub strength: f32,
+}
+
+impl Default for ColorGradeSettings {
+    fn default() -> Self {
+        Self {
+            lut: "luts/warm.png".to_string(),
+            strength: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct VignetteSettings {
+    pub strength: f32,
+    /// Distance from the centre where darkening starts, 1.0 is a corner.
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            strength: 0.4,
+            radius: 0.5,
+            softness: 0.6,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CrtSettings {
+    /// How dark every other row gets.
+    pub scanlines: f32,
+    /// Barrel distortion of the screen.
+    pub curvature: f32,
+    /// Screen pixels per blocky pixel.
+    pub pixel_size: f32,
+}
+
+impl Default for CrtSettings {
+    fn default() -> Self {
+        Self {
+            scanlines: 0.25,
+            curvature: 0.04,
+            pixel_size: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum PostEffect {
+    Bloom(BloomSettings),
+    Tonemap(TonemapSettings),
+    ColorGrade(ColorGradeSettings),
+    Vignette(VignetteSettings),
+    Crt(CrtSettings),
+}
+
+impl PostEffect {
+    /// Name used in the config and in `toggle_post_<name>` actions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostEffect::Bloom(_) => "bloom",
+            PostEffect::Tonemap(_) => "tonemap",
+            PostEffect::ColorGrade(_) => "color_grade",
+            PostEffect::Vignette(_) => "vignette",
+            PostEffect::Crt(_) => "crt",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct PostPass {
+    #[serde(default = "default_pass_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub effect: PostEffect,
+}
+
+fn default_pass_enabled() -> bool {
+    true
+}
+
+/// Passes run in order over the scene, the last one draws to the screen.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    pub enabled: bool,
+    pub passes: Vec<PostPass>,
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        let pass = |enabled, effect| PostPass { enabled, effect };
+        Self {
+            enabled: true,
+            passes: vec![
+                pass(true, PostEffect::Bloom(BloomSettings::default())),
+                pass(true, PostEffect::Tonemap(TonemapSettings::default())),
+                pass(false, PostEffect::ColorGrade(ColorGradeSettings::default())),
+                pass(true, PostEffect::Vignette(VignetteSettings::default())),
+                pass(false, PostEffect::Crt(CrtSettings::default())),
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct PostUniform {
+    params: [f32; 4],
+    texel_size: [f32; 2],
+    resolution: [f32; 2],
+}
+
+struct Target {
+    texture: Texture,
+    view: TextureView,
+}
+
+impl Target {
+    fn new(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    fn size(&self) -> [f32; 2] {
+        [self.texture.width() as f32, self.texture.height() as f32]
+    }
+}
+
+/// Textures a pass reads from or draws to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Slot {
+    Scene(usize),
+    Bloom(usize),
+    /// LUT of the pass at this index.
+    Lut(usize),
+    Blank,
+}
+
+/// One fullscreen draw of a fragment entry point of `post_process.wgsl`.
+struct Step {
+    entry_point: &'static str,
+    source: Slot,
+    extra: Slot,
+    /// `None` draws to the screen.
+    target: Option<Slot>,
+    params: [f32; 4],
+}
+
+/// Runs the configured post-process passes over the HDR scene and draws
+/// the result to the screen.
+///
+/// The scene is drawn into `scene_view`, passes then ping-pong between two
+/// full size HDR textures, with bloom blurred at half size.
+pub struct PostProcessor {
+    config: PostProcessConfig,
+    surface_format: TextureFormat,
+    shader: wgpu::ShaderModule,
+    layout: BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<(&'static str, TextureFormat), RenderPipeline>,
+    sampler: wgpu::Sampler,
+    uniform_buffer: Buffer,
+    uniform_stride: u64,
+    uniform_capacity: usize,
+    bind_groups: HashMap<(Slot, Slot), BindGroup>,
+    scene: [Target; 2],
+    bloom: [Target; 2],
+    luts: HashMap<usize, (Target, u32)>,
+    blank: Target,
+}
+
+impl PostProcessor {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface_format: TextureFormat,
+        width: u32,
+        height: u32,
+        config: PostProcessConfig,
+        asset_root: &Path,
+    ) -> Self {
+        let shader_path = asset_root.join("shaders/post_process.wgsl");
+        let shader_file = match fs::read_to_string(&shader_path) {
+            Ok(str) => str,
+            Err(_) => panic!("could't load shader at path: {} ", shader_path.display()),
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post-process shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_file.into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<PostUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post-process Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post-process Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride =
+            (std::mem::size_of::<PostUniform>() as u64).next_multiple_of(alignment);
+        let uniform_capacity = 16;
+
+        let blank = Target::new(
+            device,
+            "Post-process Blank",
+            1,
+            1,
+            TextureFormat::Rgba8Unorm,
+        );
+        let mut post_process = Self {
+            config: PostProcessConfig::default(),
+            surface_format,
+            shader,
+            layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            sampler,
+            uniform_buffer: PostProcessor::create_uniform_buffer(
+                device,
+                uniform_stride,
+                uniform_capacity,
+            ),
+            uniform_stride,
+            uniform_capacity,
+            bind_groups: HashMap::new(),
+            scene: PostProcessor::create_scene_targets(device, width, height),
+            bloom: PostProcessor::create_bloom_targets(device, width, height),
+            luts: HashMap::new(),
+            blank,
+        };
+        post_process.set_config(device, queue, config, asset_root);
+        post_process
+    }
+
+    fn create_uniform_buffer(device: &wgpu::Device, stride: u64, capacity: usize) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post-process Uniform Buffer"),
+            size: stride * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_scene_targets(device: &wgpu::Device, width: u32, height: u32) -> [Target; 2] {
+        [0, 1].map(|_| Target::new(device, "Post-process Scene", width, height, HDR_FORMAT))
+    }
+
+    fn create_bloom_targets(device: &wgpu::Device, width: u32, height: u32) -> [Target; 2] {
+        [0, 1].map(|_| {
+            Target::new(
+                device,
+                "Post-process Bloom",
+                width / 2,
+                height / 2,
+                HDR_FORMAT,
+            )
+        })
+    }
+
+    pub fn config(&self) -> &PostProcessConfig {
+        &self.config
+    }
+
+    /// Replaces the passes, loading the LUT of every colour grading pass.
+    /// Passes whose LUT can't be loaded are disabled.
+    pub fn set_config(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut config: PostProcessConfig,
+        asset_root: &Path,
+    ) {
+        self.luts.clear();
+        self.bind_groups.clear();
+        for (index, pass) in config.passes.iter_mut().enumerate() {
+            let PostEffect::ColorGrade(settings) = &pass.effect else {
+                continue;
+            };
+            match PostProcessor::load_lut(device, queue, &asset_root.join(&settings.lut)) {
+                Ok(lut) => {
+                    self.luts.insert(index, lut);
+                }
+                Err(e) => {
+                    warn!("couldn't load LUT {}: {}", settings.lut, e);
+                    pass.enabled = false;
+                }
+            }
+        }
+        self.config = config;
+    }
+
+    fn load_lut(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+    ) -> Result<(Target, u32), String> {
+        let image = RgbaImage::load_png(path).map_err(|e| e.to_string())?;
+        let size = image.height;
+        if size < 2 || image.width != size * size {
+            return Err(format!(
+                "expected a strip of N slices of N x N, got {}x{}",
+                image.width, image.height
+            ));
+        }
+        let lut = Target::new(
+            device,
+            "Post-process LUT",
+            image.width,
+            image.height,
+            TextureFormat::Rgba8Unorm,
+        );
+        queue.write_texture(
+            lut.texture.as_image_copy(),
+            bytemuck::cast_slice(&image.pixels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * image.width),
+                rows_per_image: None,
+            },
+            lut.texture.size(),
+        );
+        Ok((lut, size))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Turns the whole chain on or off, the scene is still drawn through
+    /// the HDR target when it's off.
+    pub fn toggle(&mut self) {
+        self.config.enabled = !self.config.enabled;
+        info!("post-processing enabled: {}", self.config.enabled);
+    }
+
+    /// Turns every pass of the effect called `name` on or off. Returns
+    /// false when there is no such pass.
+    pub fn toggle_effect(&mut self, name: &str) -> bool {
+        let mut found = false;
+        for (index, pass) in self.config.passes.iter_mut().enumerate() {
+            if pass.effect.name() != name {
+                continue;
+            }
+            found = true;
+            if matches!(pass.effect, PostEffect::ColorGrade(_)) && !self.luts.contains_key(&index) {
+                continue;
+            }
+            pass.enabled = !pass.enabled;
+            info!("post-process {} enabled: {}", name, pass.enabled);
+        }
+        found
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.scene = PostProcessor::create_scene_targets(device, width, height);
+        self.bloom = PostProcessor::create_bloom_targets(device, width, height);
+        self.bind_groups.clear();
+    }
+
+    /// Where the scene is drawn each frame, in `HDR_FORMAT`.
+    pub fn scene_view(&self) -> &TextureView {
+        &self.scene[0].view
+    }
+
+    fn target(&self, slot: Slot) -> &Target {
+        match slot {
+            Slot::Scene(i) => &self.scene[i],
+            Slot::Bloom(i) => &self.bloom[i],
+            Slot::Lut(pass) => self.luts.get(&pass).map_or(&self.blank, |(lut, _)| lut),
+            Slot::Blank => &self.blank,
+        }
+    }
+
+    /// Draws of the enabled passes, the scene starts in `Slot::Scene(0)`.
+    fn steps(&self) -> Vec<Step> {
+        let passes: Vec<(usize, &PostPass)> = match self.config.enabled {
+            true => self
+                .config
+                .passes
+                .iter()
+                .enumerate()
+                .filter(|(_, pass)| pass.enabled)
+                .collect(),
+            false => vec![],
+        };
+
+        let mut steps = vec![];
+        let mut current = 0;
+        for (position, (index, pass)) in passes.iter().enumerate() {
+            let source = Slot::Scene(current);
+            let target = match position + 1 == passes.len() {
+                true => None,
+                false => Some(Slot::Scene(1 - current)),
+            };
+            let step = |entry_point, extra, params| Step {
+                entry_point,
+                source,
+                extra,
+                target,
+                params,
+            };
+            match &pass.effect {
+                PostEffect::Bloom(bloom) => {
+                    let blur = |source, target, direction: [f32; 2]| Step {
+                        entry_point: "fs_blur",
+                        source,
+                        extra: Slot::Blank,
+                        target: Some(target),
+                        params: [direction[0], direction[1], bloom.radius, 0.0],
+                    };
+                    steps.push(Step {
+                        entry_point: "fs_bright",
+                        source,
+                        extra: Slot::Blank,
+                        target: Some(Slot::Bloom(0)),
+                        params: [bloom.threshold, bloom.knee, 0.0, 0.0],
+                    });
+                    steps.push(blur(Slot::Bloom(0), Slot::Bloom(1), [1.0, 0.0]));
+                    steps.push(blur(Slot::Bloom(1), Slot::Bloom(0), [0.0, 1.0]));
+                    steps.push(step(
+                        "fs_bloom",
+                        Slot::Bloom(0),
+                        [bloom.intensity, 0.0, 0.0, 0.0],
+                    ));
+                }
+                PostEffect::Tonemap(tonemap) => steps.push(step(
+                    "fs_tonemap",
+                    Slot::Blank,
+                    [tonemap.exposure, 0.0, 0.0, 0.0],
+                )),
+                PostEffect::ColorGrade(grade) => {
+                    let size = self.luts.get(index).map_or(2, |(_, size)| *size);
+                    steps.push(step(
+                        "fs_color_grade",
+                        Slot::Lut(*index),
+                        [grade.strength, size as f32, 0.0, 0.0],
+                    ))
+                }
+                PostEffect::Vignette(vignette) => steps.push(step(
+                    "fs_vignette",
+                    Slot::Blank,
+                    [vignette.strength, vignette.radius, vignette.softness, 0.0],
+                )),
+                PostEffect::Crt(crt) => steps.push(step(
+                    "fs_crt",
+                    Slot::Blank,
+                    [crt.scanlines, crt.curvature, crt.pixel_size, 0.0],
+                )),
+            }
+            current = 1 - current;
+        }
+
+        if steps.is_empty() {
+            steps.push(Step {
+                entry_point: "fs_blit",
+                source: Slot::Scene(0),
+                extra: Slot::Blank,
+                target: None,
+                params: [0.0; 4],
+            });
+        }
+        steps
+    }
+
+    fn pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        entry_point: &'static str,
+        format: TextureFormat,
+    ) {
+        if self.pipelines.contains_key(&(entry_point, format)) {
+            return;
+        }
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: Default::default(),
+        });
+        self.pipelines.insert((entry_point, format), pipeline);
+    }
+
+    fn bind_group(&mut self, device: &wgpu::Device, source: Slot, extra: Slot) {
+        if self.bind_groups.contains_key(&(source, extra)) {
+            return;
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.uniform_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<PostUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.target(source).view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.target(extra).view),
+                },
+            ],
+            label: Some("post_process_bind_group"),
+        });
+        self.bind_groups.insert((source, extra), bind_group);
+    }
+
+    /// Records the passes over the scene into `encoder`, the last one
+    /// drawing to `output` of `output_size`.
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &TextureView,
+        output_size: [f32; 2],
+    ) {
+        let steps = self.steps();
+
+        if steps.len() > self.uniform_capacity {
+            self.uniform_capacity = steps.len().next_power_of_two();
+            self.uniform_buffer.destroy();
+            self.uniform_buffer = PostProcessor::create_uniform_buffer(
+                device,
+                self.uniform_stride,
+                self.uniform_capacity,
+            );
+            self.bind_groups.clear();
+        }
+
+        let mut uniforms = vec![0u8; self.uniform_stride as usize * steps.len()];
+        for (i, step) in steps.iter().enumerate() {
+            let [width, height] = self.target(step.source).size();
+            let uniform = PostUniform {
+                params: step.params,
+                texel_size: [1.0 / width, 1.0 / height],
+                resolution: step
+                    .target
+                    .map_or(output_size, |slot| self.target(slot).size()),
+            };
+            let offset = i * self.uniform_stride as usize;
+            uniforms[offset..offset + std::mem::size_of::<PostUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &uniforms);
+
+        for step in &steps {
+            let format = step.target.map_or(self.surface_format, |_| HDR_FORMAT);
+            self.pipeline(device, step.entry_point, format);
+            self.bind_group(device, step.source, step.extra);
+        }
+
+        for (i, step) in steps.iter().enumerate() {
+            let view = match step.target {
+                Some(slot) => &self.target(slot).view,
+                None => output,
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(step.entry_point),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                ..Default::default()
+            });
+            let format = step.target.map_or(self.surface_format, |_| HDR_FORMAT);
+            render_pass.set_pipeline(&self.pipelines[&(step.entry_point, format)]);
+            render_pass.set_bind_group(
+                0,
+                &self.bind_groups[&(step.source, step.extra)],
+                &[(i as u64 * self.uniform_stride) as u32],
+            );
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use core::panic;
+use glam::{Mat4, Vec2, Vec4};
+use log::{debug, info, warn};
+use std::{fs, path::Path, time::Instant};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, Buffer, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    chunk_renderer::ChunkRenderer,
+    constants::{Vertex, INDICES, VERTICES},
+    engine_config::EngineConfig,
+    enums::cell_assets::CellAssets,
+    instance_buffer::InstanceBuffer,
+    instance_data::{InstanceData, Palette},
+    lighting::{LightRenderer, LightingConfig},
+    post_process::{PostProcessor, HDR_FORMAT},
+    utils::rects_overlap,
+    world::World,
+};
+
+/// Backends to look for adapters on, `WGPU_BACKEND` overrides them. GL is
+/// included for the software rasterizers found on machines without a GPU.
+pub fn backends() -> wgpu::Backends {
+    wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all())
+}
+
+/// Asks for a hardware adapter first, then for a software one, which is
+/// slow but draws the same frames. Only the software one is tried when
+/// `force_fallback` is set.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+    force_fallback: bool,
+) -> Result<wgpu::Adapter, String> {
+    let options = |force_fallback_adapter| wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: surface,
+        force_fallback_adapter,
+    };
+    if !force_fallback {
+        if let Some(adapter) = instance.request_adapter(&options(false)).await {
+            return Ok(adapter);
+        }
+        warn!("no hardware graphics adapter found, trying a software one");
+    }
+    instance
+        .request_adapter(&options(true))
+        .await
+        .ok_or_else(|| {
+            format!(
+                "no graphics adapter found on {:?}{}; check that your GPU drivers are \
+                 installed, or install a software rasterizer such as Mesa's llvmpipe",
+                backends(),
+                match surface {
+                    Some(_) => " that can draw to this window",
+                    None => "",
+                },
+            )
+        })
+}
+
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), String> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::empty(),
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web, we'll have to disable some.
+                required_limits: wgpu::Limits::default(),
+
+                label: None,
+                memory_hints: Default::default(),
+            },
+            None, // Trace path
+        )
+        .await
+        .map_err(|e| {
+            format!(
+                "couldn't open the graphics device {}: {}",
+                adapter.get_info().name,
+                e
+            )
+        })
+}
+
+/// Everything that draws a `World` frame: cell chunks, lighting, object
+/// instances and post-processing into an output texture. Used by the window
+/// and by `OffscreenRenderer`.
+pub struct Renderer {
+    pub render_pipeline: RenderPipeline,
+    pub chunk_renderer: ChunkRenderer,
+    pub light_renderer: LightRenderer,
+    pub post_process: PostProcessor,
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub num_vertices: u32,
+    pub num_indices: u32,
+    /// Static instances drawn on top of the world objects.
+    pub instances: Vec<InstanceData>,
+    pub instance_buffer: InstanceBuffer,
+    pub colors_buffer: Buffer,
+    /// Colours currently in `colors_buffer`.
+    pub palette: Palette,
+    pub camera_bind_group: BindGroup,
+    pub camera_bind_group_layout: BindGroupLayout,
+}
+
+impl Renderer {
+    fn create_colors_buffer(device: &wgpu::Device, palette: &Palette) -> Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Colors Buffer"),
+            contents: bytemuck::cast_slice(&palette.values),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    fn create_camera_bind_group(
+        device: &wgpu::Device,
+        layout: &BindGroupLayout,
+        camera_buffer: &Buffer,
+        colors_buffer: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: colors_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("camera_colors_bind_group"),
+        })
+    }
+
+    fn init_render_pipeline(
+        device: &wgpu::Device,
+        colors_buffer: &Buffer,
+        asset_root: &Path,
+    ) -> (RenderPipeline, BindGroup, BindGroupLayout, Buffer) {
+        let shader_path = asset_root.join("shaders/shader.wgsl");
+        let shader_file = {
+            match fs::read_to_string(&shader_path) {
+                Ok(str) => str,
+                Err(_) => {
+                    panic!("could't load shader at path: {} ", shader_path.display())
+                }
+            }
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_file.into()),
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("camera_bind_group_layout"),
+            });
+
+        let camera_uniform = CameraUniform {
+            view_proj: Mat4::ZERO.to_cols_array_2d(),
+            position: Vec4::ZERO,
+        };
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group = Renderer::create_camera_bind_group(
+            device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            colors_buffer,
+        );
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main", // 1.
+                buffers: &[Vertex::desc(), InstanceData::desc()],
+                compilation_options: Default::default(), // 2.
+            },
+            fragment: Some(wgpu::FragmentState {
+                // 3.
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    // 4.
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw, // 2.
+                cull_mode: Some(wgpu::Face::Back),
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: None, // 1.
+            multisample: wgpu::MultisampleState {
+                count: 1,                         // 2.
+                mask: !0,                         // 3.
+                alpha_to_coverage_enabled: false, // 4.
+            },
+            multiview: None,
+            cache: Default::default(), // 5.
+        });
+
+        (
+            render_pipeline,
+            camera_bind_group,
+            camera_bind_group_layout,
+            camera_buffer,
+        )
+    }
+
+    /// Sets up drawing into `output_format` textures of `width` x `height`,
+    /// with the camera looking at the origin.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: TextureFormat,
+        width: u32,
+        height: u32,
+        assets: &CellAssets,
+        engine_config: &EngineConfig,
+    ) -> (Self, Camera) {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let num_vertices = VERTICES.len() as u32;
+        let num_indices = INDICES.len() as u32;
+
+        let palette = Palette::from_assets(assets);
+        let colors_buffer = Renderer::create_colors_buffer(device, &palette);
+
+        let (render_pipeline, camera_bind_group, camera_bind_group_layout, camera_buffer) =
+            Renderer::init_render_pipeline(device, &colors_buffer, &engine_config.asset_root);
+
+        let chunk_renderer = ChunkRenderer::new(
+            device,
+            HDR_FORMAT,
+            &camera_bind_group_layout,
+            &engine_config.asset_root,
+        );
+
+        let light_renderer = LightRenderer::new(
+            device,
+            HDR_FORMAT,
+            &camera_bind_group_layout,
+            assets,
+            &engine_config.asset_root,
+        );
+
+        let post_process = PostProcessor::new(
+            device,
+            queue,
+            output_format,
+            width,
+            height,
+            engine_config.post_process.clone(),
+            &engine_config.asset_root,
+        );
+
+        let camera = Camera::create_camera_from_screen_size(
+            width as f32,
+            height as f32,
+            0.1,
+            100.0,
+            0.0,
+            Vec2::ZERO,
+            camera_buffer,
+        );
+
+        let instances = {
+            let mut instances = vec![];
+            for x in -10..10 {
+                for y in -10..10 {
+                    instances.push(InstanceData {
+                        position: Vec2::new(x as f32, y as f32),
+                        color: 0,
+                    })
+                }
+            }
+            instances
+        };
+
+        let instance_buffer = InstanceBuffer::new(device);
+
+        let renderer = Self {
+            render_pipeline,
+            chunk_renderer,
+            light_renderer,
+            post_process,
+            vertex_buffer,
+            index_buffer,
+            num_vertices,
+            num_indices,
+            instances,
+            instance_buffer,
+            colors_buffer,
+            palette,
+            camera_bind_group,
+            camera_bind_group_layout,
+        };
+        (renderer, camera)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.post_process.resize(device, width, height);
+    }
+
+    /// Re-renders the objects whose instances may have changed and sends
+    /// only the slices that did.
+    pub fn update_instance_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        world: &World,
+        camera: &Camera,
+        alpha: f32,
+    ) {
+        let view = camera.visible_rect();
+        let mut culled = 0;
+        for (index, obj) in world.storage.iter().enumerate() {
+            if obj
+                .bounds()
+                .is_some_and(|bounds| !rects_overlap(bounds, view))
+            {
+                self.instance_buffer.set(index, None, vec![]);
+                culled += 1;
+                continue;
+            }
+            let revision = obj.render_revision();
+            if self.instance_buffer.is_current(index, revision) {
+                continue;
+            }
+            let now = Instant::now();
+            let instances = obj.render_interpolated(alpha);
+            let elapsed_time = now.elapsed();
+            info!(
+                "Running render() took {} seconds for {}.",
+                elapsed_time.as_secs_f32(),
+                obj.get_name(),
+            );
+            self.instance_buffer.set(index, revision, instances);
+        }
+
+        let static_index = world.storage.len();
+        if !self.instance_buffer.is_current(static_index, Some(0)) {
+            self.instance_buffer
+                .set(static_index, Some(0), self.instances.clone());
+        }
+        self.instance_buffer.truncate(static_index + 1);
+
+        let now = Instant::now();
+        let sent = self.instance_buffer.upload(device, queue);
+        let elapsed_time = now.elapsed();
+        info!(
+            "Running update_instance_buffer() took {}, instances rendered {}, sent {}, objects culled {}.",
+            elapsed_time.as_secs_f32(),
+            self.instance_buffer.len(),
+            sent,
+            culled
+        );
+    }
+
+    /// Sends the material colours to the GPU when the assets changed,
+    /// growing the buffer when there are more materials than it holds.
+    pub fn update_colors_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &CellAssets,
+        camera: &Camera,
+    ) {
+        let palette = Palette::from_assets(assets);
+        if palette == self.palette {
+            return;
+        }
+        if palette.size_bytes() > self.colors_buffer.size() {
+            info!("palette grew to {} colours", palette.values.len());
+            self.colors_buffer.destroy();
+            self.colors_buffer = Renderer::create_colors_buffer(device, &palette);
+            self.camera_bind_group = Renderer::create_camera_bind_group(
+                device,
+                &self.camera_bind_group_layout,
+                &camera.camera_buffer,
+                &self.colors_buffer,
+            );
+        } else {
+            queue.write_buffer(
+                &self.colors_buffer,
+                0,
+                bytemuck::cast_slice(&palette.values),
+            );
+        }
+        self.palette = palette;
+    }
+
+    /// Draws `world` as seen by `camera` into `output`, a texture of the
+    /// format and size the renderer was set up for. `alpha` interpolates
+    /// objects between the last two ticks.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        world: &mut World,
+        camera: &mut Camera,
+        alpha: f32,
+        lighting: &LightingConfig,
+        output: &TextureView,
+        output_size: [f32; 2],
+    ) {
+        camera.update_camera_buffer(queue);
+        self.update_colors_buffer(device, queue, &world.assets, camera);
+        self.chunk_renderer.update_materials(device, &world.assets);
+        self.update_instance_buffer(device, queue, world, camera, alpha);
+        self.chunk_renderer.update(device, queue, world);
+        debug!("chunks culled {}", self.chunk_renderer.culled_chunks);
+        if lighting.enabled {
+            self.light_renderer.update(
+                device,
+                queue,
+                world,
+                camera.visible_rect(),
+                lighting,
+                &self.chunk_renderer.changed_chunks,
+            );
+        } else {
+            self.light_renderer.invalidate();
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    // This is what @location(0) in the fragment shader targets
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: self.post_process.scene_view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.0,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 1.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: None,
+                ..Default::default()
+            });
+
+            self.chunk_renderer.draw(
+                &mut render_pass,
+                &self.camera_bind_group,
+                &self.vertex_buffer,
+                &self.index_buffer,
+                self.num_indices,
+            );
+            if lighting.enabled {
+                self.light_renderer
+                    .draw(&mut render_pass, &self.camera_bind_group);
+            }
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            self.instance_buffer
+                .draw(&mut render_pass, self.num_indices);
+        }
+
+        self.post_process
+            .draw(device, queue, &mut encoder, output, output_size);
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
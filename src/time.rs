@@ -0,0 +1,217 @@
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+/// Frame times above this are treated as a hitch (debugger, window drag) and clamped.
+pub const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Most simulation ticks run in one frame before the backlog is dropped.
+pub const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// Turns measured frame times into a whole number of fixed simulation ticks.
+///
+/// Leftover time is kept in the accumulator for the next frame and exposed as
+/// `alpha` so rendering can interpolate between the last two ticks.
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulator: Duration,
+    last_frame: Option<Instant>,
+    frame_time: Duration,
+    pub max_ticks_per_frame: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            tick: Duration::from_secs_f32(1.0 / tick_rate),
+            accumulator: Duration::ZERO,
+            last_frame: None,
+            frame_time: Duration::ZERO,
+            max_ticks_per_frame: MAX_TICKS_PER_FRAME,
+        }
+    }
+
+    /// Length of one simulation tick in seconds.
+    pub fn tick_secs(&self) -> f32 {
+        self.tick.as_secs_f32()
+    }
+
+    /// Time between the last two frames in seconds.
+    pub fn frame_secs(&self) -> f32 {
+        self.frame_time.as_secs_f32()
+    }
+
+    /// Measures the time since the previous call, clamped to `MAX_FRAME_TIME`.
+    pub fn measure_frame(&mut self) -> Duration {
+        let now = Instant::now();
+        let frame_time = match self.last_frame {
+            Some(last) => now - last,
+            None => Duration::ZERO,
+        };
+        self.last_frame = Some(now);
+        self.frame_time = frame_time.min(MAX_FRAME_TIME);
+        self.frame_time
+    }
+
+    /// Adds `sim_time` to the accumulator and returns the number of ticks
+    /// it covers, never more than `max_ticks_per_frame`.
+    pub fn advance(&mut self, sim_time: Duration) -> u32 {
+        self.accumulator += sim_time;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            if ticks == self.max_ticks_per_frame {
+                // The simulation can't keep up, drop the backlog instead of
+                // running ever more ticks per frame.
+                let tick = self.tick.as_nanos();
+                let backlog = self.accumulator.as_nanos();
+                debug!("simulation is behind, dropping {} ticks", backlog / tick);
+                self.accumulator = Duration::from_nanos((backlog % tick) as u64);
+                break;
+            }
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far rendering is between the previous tick (0.0) and the latest one (1.0).
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f32() / self.tick.as_secs_f32()).clamp(0.0, 1.0)
+    }
+}
+
+pub const MIN_TIME_SCALE: f32 = 0.125;
+pub const MAX_TIME_SCALE: f32 = 8.0;
+
+/// Engine level pause, single-step and time scale, applied to every
+/// `WorldObject` by deciding how many ticks run each frame.
+pub struct SimulationClock {
+    paused: bool,
+    time_scale: f32,
+    pending_steps: u32,
+    /// Ticks run since the world was created.
+    pub tick_count: u64,
+}
+
+impl Default for SimulationClock {
+    /// Starts paused, like the cell world did before the clock existed.
+    fn default() -> Self {
+        Self {
+            paused: true,
+            time_scale: 1.0,
+            pending_steps: 0,
+            tick_count: 0,
+        }
+    }
+}
+
+impl SimulationClock {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        info!("simulation {}", if paused { "paused" } else { "resumed" });
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    /// Pauses the simulation and queues `ticks` ticks for the next frame.
+    pub fn step(&mut self, ticks: u32) {
+        self.paused = true;
+        self.pending_steps += ticks;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Sets the simulation speed relative to real time, clamped to
+    /// `MIN_TIME_SCALE..=MAX_TIME_SCALE`.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+        info!("time scale {}", self.time_scale);
+    }
+
+    /// Number of ticks to run for a frame that took `frame_time`.
+    pub fn ticks_for_frame(&mut self, timestep: &mut FixedTimestep, frame_time: Duration) -> u32 {
+        let ticks = if self.paused {
+            std::mem::take(&mut self.pending_steps)
+        } else {
+            timestep.advance(frame_time.mul_f32(self.time_scale))
+        };
+        self.tick_count += ticks as u64;
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_micros(62_500);
+
+    fn timestep() -> FixedTimestep {
+        // 16 ticks per second, one tick per `FRAME` without rounding.
+        FixedTimestep::new(16.0)
+    }
+
+    #[test]
+    fn starts_paused() {
+        let mut clock = SimulationClock::default();
+        assert!(clock.is_paused());
+        assert_eq!(clock.ticks_for_frame(&mut timestep(), FRAME), 0);
+    }
+
+    #[test]
+    fn runs_one_tick_per_tick_length() {
+        let mut timestep = timestep();
+        let mut clock = SimulationClock::default();
+        clock.set_paused(false);
+        let ticks: u32 = (0..10)
+            .map(|_| clock.ticks_for_frame(&mut timestep, FRAME))
+            .sum();
+        assert_eq!(ticks, 10);
+        assert_eq!(clock.tick_count, 10);
+    }
+
+    #[test]
+    fn step_runs_queued_ticks_once() {
+        let mut timestep = timestep();
+        let mut clock = SimulationClock::default();
+        clock.set_paused(false);
+        clock.step(3);
+        assert!(clock.is_paused());
+        assert_eq!(clock.ticks_for_frame(&mut timestep, FRAME), 3);
+        assert_eq!(clock.ticks_for_frame(&mut timestep, FRAME), 0);
+    }
+
+    #[test]
+    fn time_scale_changes_tick_rate() {
+        let mut timestep = timestep();
+        let mut clock = SimulationClock::default();
+        clock.set_paused(false);
+        clock.set_time_scale(2.0);
+        assert_eq!(clock.ticks_for_frame(&mut timestep, FRAME), 2);
+
+        clock.set_time_scale(0.5);
+        let ticks: u32 = (0..4)
+            .map(|_| clock.ticks_for_frame(&mut timestep, FRAME))
+            .sum();
+        assert_eq!(ticks, 2);
+
+        clock.set_time_scale(100.0);
+        assert_eq!(clock.time_scale(), MAX_TIME_SCALE);
+    }
+
+    #[test]
+    fn backlog_is_dropped_after_max_ticks() {
+        let mut timestep = timestep();
+        assert_eq!(timestep.advance(FRAME * 20), MAX_TICKS_PER_FRAME);
+        assert_eq!(timestep.advance(Duration::ZERO), 0);
+    }
+}
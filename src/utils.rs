@@ -34,6 +34,7 @@
 // }
 
 use ecolor::Rgba;
+use glam::{IVec2, Vec2};
 
 pub fn normalize_color(color: Rgba) -> Rgba {
     Rgba::from_rgba_premultiplied(
@@ -43,3 +44,44 @@ pub fn normalize_color(color: Rgba) -> Rgba {
         color.a() / 255.0,
     )
 }
+
+/// Cells on the line from `from` to `to`, both ends included (Bresenham).
+pub fn line_cells(from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let delta = (to - from).abs();
+    let step = (to - from).signum();
+    let mut error = delta.x - delta.y;
+    let mut current = from;
+    let mut cells = Vec::with_capacity(delta.max_element() as usize + 1);
+    loop {
+        cells.push(current);
+        if current == to {
+            return cells;
+        }
+        let doubled = error * 2;
+        if doubled > -delta.y {
+            error -= delta.y;
+            current.x += step.x;
+        }
+        if doubled < delta.x {
+            error += delta.x;
+            current.y += step.y;
+        }
+    }
+}
+
+/// Whether the rectangles `a` and `b`, given as smallest and largest
+/// corner, overlap.
+pub fn rects_overlap(a: (Vec2, Vec2), b: (Vec2, Vec2)) -> bool {
+    a.0.x <= b.1.x && b.0.x <= a.1.x && a.0.y <= b.1.y && b.0.y <= a.1.y
+}
+
+/// Shade of a cell spawned at `pos`, never 0 so it can't be mistaken for
+/// an unset shade.
+pub fn cell_shade(pos: IVec2) -> u8 {
+    let mut h =
+        ((pos.x as u32 as u64) << 32 | pos.y as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 32;
+    (h % 255) as u8 + 1
+}
@@ -1,8 +1,18 @@
-use crate::enums::cell_assets::CellAssets;
+use crate::chunk_renderer::ChunkSource;
+use crate::engine_config::EngineConfig;
+use crate::enums::cell_assets::{import_config, CellAssets};
+use crate::objects::sand::generator::{NoiseGenerator, NoiseGeneratorConfig};
+use crate::objects::sand::stamp::{StampLibrary, STAMP_DIR};
 use crate::objects::Player;
-use crate::{instance_data::InstanceData, objects::sand::CellWorld};
+use crate::time::{FixedTimestep, SimulationClock};
+use crate::{
+    input::{ActionEvent, InputState},
+    instance_data::InstanceData,
+    objects::sand::CellWorld,
+};
 use glam::Vec2;
-use log::info;
+use log::{info, warn};
+use std::{sync::Arc, time::Duration};
 use winit::event::WindowEvent;
 
 pub struct WorldObjectContainer {
@@ -13,6 +23,7 @@ pub struct WorldObjectContainer {
 pub struct World {
     pub storage: Vec<Box<dyn WorldObject>>,
     pub assets: CellAssets,
+    pub clock: SimulationClock,
 }
 
 impl World {
@@ -22,6 +33,16 @@ impl World {
         }
     }
 
+    /// Runs the ticks owed for a frame that took `frame_time`, honouring the
+    /// clock's pause, steps and time scale. Returns the number of ticks run.
+    pub fn run_frame(&mut self, timestep: &mut FixedTimestep, frame_time: Duration) -> u32 {
+        let ticks = self.clock.ticks_for_frame(timestep, frame_time);
+        for _ in 0..ticks {
+            self.update(timestep.tick_secs());
+        }
+        ticks
+    }
+
     pub fn input(&mut self, delta_t: f32, event: &WindowEvent, mouse_position: Vec2) -> bool {
         for object in self.storage.iter_mut() {
             object.input(delta_t, event, mouse_position);
@@ -29,40 +50,112 @@ impl World {
         true
     }
 
+    pub fn poll_input(&mut self, input: &InputState) {
+        for object in self.storage.iter_mut() {
+            object.poll_input(input);
+        }
+    }
+
+    /// Passes an action to every object listening to its context.
+    pub fn action(&mut self, action: &ActionEvent, mouse_position: Vec2) {
+        for object in self.storage.iter_mut() {
+            if object.input_context() == Some(action.context.as_str()) {
+                object.on_action(action, mouse_position);
+            }
+        }
+    }
+
+    pub fn set_focus(&mut self, focus: Vec2) {
+        for object in self.storage.iter_mut() {
+            object.set_focus(focus);
+        }
+    }
+
+    /// Passes the part of the world on screen to every object.
+    pub fn set_view(&mut self, view: (Vec2, Vec2)) {
+        for object in self.storage.iter_mut() {
+            object.set_view(view);
+        }
+    }
+
     pub fn add_obj(&mut self, obj: Box<dyn WorldObject>) {
         self.storage.push(obj)
     }
 
-    pub fn init_world(assets: CellAssets) -> Self {
+    /// Loads the generator referenced by the asset `config.toml`, if any,
+    /// with the seed from `config` applied.
+    pub fn load_generator(assets: &CellAssets, config: &EngineConfig) -> Option<NoiseGenerator> {
+        let path = import_config(&config.asset_root)?.generator?;
+        let generator_config = NoiseGeneratorConfig::load(config.asset_path(path))?;
+        match NoiseGenerator::new(generator_config, assets) {
+            Ok(generator) => Some(match config.seed {
+                Some(seed) => generator.with_seed(seed),
+                None => generator,
+            }),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Builds the cell world described by `config`: generated terrain when a
+    /// generator is configured, then the starting save on top.
+    pub fn init_cell_world(
+        assets: &CellAssets,
+        config: &EngineConfig,
+    ) -> Result<CellWorld, String> {
+        let mut cell_world = match World::load_generator(assets, config) {
+            Some(generator) => CellWorld::generated(assets.clone(), Arc::new(generator)),
+            None => match config.seed {
+                Some(seed) => {
+                    warn!(
+                        "no world generator configured, seed {} only seeds the physics",
+                        seed
+                    );
+                    CellWorld::new(assets.clone()).with_seed(seed)
+                }
+                None => CellWorld::new(assets.clone()),
+            },
+        }
+        .with_streaming(config.streaming.clone());
+        cell_world.stamps = StampLibrary::load_dir(config.asset_path(STAMP_DIR));
+
+        if let Some(path) = &config.start_save {
+            cell_world
+                .load(path)
+                .map_err(|e| format!("couldn't load save {}: {}", path.display(), e))?;
+        }
+        Ok(cell_world)
+    }
+
+    pub fn init_world(assets: CellAssets, config: &EngineConfig) -> Result<Self, String> {
         let mut world = World {
             storage: Default::default(),
             assets: assets.clone(),
+            clock: SimulationClock::default(),
         };
 
-        let player_obj: Box<dyn WorldObject> = Box::new(Player {
-            name: "Main player".to_string(),
-            position: Vec2::new(-20.0, -30.0),
-        });
+        let player_obj: Box<dyn WorldObject> =
+            Box::new(Player::new("Main player", Vec2::new(-20.0, -30.0)));
 
         world.add_obj(player_obj);
 
-        let player_obj: Box<dyn WorldObject> = Box::new(Player {
-            name: "Main player".to_string(),
-            position: Vec2::new(20.0, 50.0),
-        });
+        let player_obj: Box<dyn WorldObject> =
+            Box::new(Player::new("Main player", Vec2::new(20.0, 50.0)));
 
         world.add_obj(player_obj);
 
-        let sand = Box::new(CellWorld::new(assets.clone()));
+        let sand = Box::new(World::init_cell_world(&assets, config)?);
 
         world.add_obj(sand);
 
-        world
+        Ok(world)
     }
 }
 
 pub trait WorldObject {
-    fn update(&mut self, delta_t: f32) {
+    fn update(&mut self, _delta_t: f32) {
         // info!(
         //     "running update for object: {}, delta_t: {}",
         //     self.get_name(),
@@ -70,7 +163,7 @@ pub trait WorldObject {
         // );
     }
 
-    fn input(&mut self, delta_t: f32, event: &WindowEvent, mouse_position: Vec2) {
+    fn input(&mut self, _delta_t: f32, event: &WindowEvent, _mouse_position: Vec2) {
         match event {
             WindowEvent::KeyboardInput { event, .. } => {
                 info!(
@@ -99,11 +192,50 @@ pub trait WorldObject {
         }
     }
 
+    /// Input context in `input.toml` whose actions this object receives.
+    fn input_context(&self) -> Option<&str> {
+        None
+    }
+
+    fn on_action(&mut self, _action: &ActionEvent, _mouse_position: Vec2) {}
+
+    /// Called once per frame before the simulation ticks, also while paused.
+    fn poll_input(&mut self, _input: &InputState) {}
+
+    /// World position the camera is looking at, used for streaming.
+    fn set_focus(&mut self, _focus: Vec2) {}
+
+    /// Smallest and largest world position on screen, for culling.
+    fn set_view(&mut self, _view: (Vec2, Vec2)) {}
+
+    /// World area covered by this object's instances. Objects outside the
+    /// view aren't rendered, `None` is always rendered.
+    fn bounds(&self) -> Option<(Vec2, Vec2)> {
+        None
+    }
+
     fn get_pos(&self) -> Vec2;
 
     fn set_pos(&mut self, pos: Vec2);
 
     fn render(&self) -> Vec<InstanceData>;
 
+    /// Instances for a frame drawn `alpha` of the way from the previous
+    /// simulation tick to the current one.
+    fn render_interpolated(&self, _alpha: f32) -> Vec<InstanceData> {
+        self.render()
+    }
+
+    /// Changes whenever `render` would return something different, so the
+    /// renderer can skip calling it. `None` renders every frame.
+    fn render_revision(&self) -> Option<u64> {
+        None
+    }
+
+    /// Chunked cell data drawn by the `ChunkRenderer` instead of as instances.
+    fn chunk_source(&mut self) -> Option<&mut dyn ChunkSource> {
+        None
+    }
+
     fn get_name(&self) -> String;
 }